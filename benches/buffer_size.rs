@@ -0,0 +1,135 @@
+//! Compares the I/O buffer sizes of `--buffer-size` with the fixed 8K pkgbump used before, on
+//! the two things it sets: reading files to hash them, and writing downloads to disk. Writes are
+//! also timed straight to the file, as downloads were written before they went through a
+//! `BufWriter`. Run with `cargo bench`.
+
+use digest::DynDigest;
+use std::io::{Error, Result};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+// The digests `Downloader` hashes SHA-1 and SHA-2 with, of which only SHA-256 is timed.
+#[allow(dead_code)]
+#[path = "../src/accel.rs"]
+mod accel;
+
+use accel::Sha256;
+
+/// The buffer size before it was configurable, and the default still.
+const OLD_BUFFER_SIZE: usize = 8 * 1024;
+const BUFFER_SIZES: &[usize] = &[OLD_BUFFER_SIZE, 64 * 1024, 1024 * 1024];
+const FILE_SIZE: usize = 64 * 1024 * 1024;
+/// About what the HTTP client hands a download at a time.
+const CHUNK_SIZE: usize = 16 * 1024;
+/// As in `Downloader`.
+const HASH_QUEUE: usize = 64;
+const RUNS: usize = 5;
+
+/// Reads `path` in `buffer_size` chunks and hashes them on a thread of their own, the way
+/// `Downloader` hashes files with `Hashing`.
+async fn hash(path: &Path, buffer_size: usize) -> Result<()> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let (chunks, mut receiver) = tokio::sync::mpsc::channel::<bytes::Bytes>(HASH_QUEUE);
+    let worker = tokio::task::spawn_blocking(move || {
+        let mut digest: Box<dyn DynDigest + Send> = Box::new(Sha256::new());
+        while let Some(chunk) = receiver.blocking_recv() {
+            digest.input(&chunk);
+        }
+        digest.result()
+    });
+    let mut buf = vec![0; buffer_size];
+    loop {
+        match file.read(&mut buf).await? {
+            0 => break,
+            len => {
+                // Only fails if the thread panicked, which awaiting it reports.
+                let _ = chunks
+                    .send(bytes::Bytes::copy_from_slice(&buf[..len]))
+                    .await;
+            }
+        }
+    }
+    drop(chunks);
+    std::hint::black_box(worker.await.map_err(Error::other)?);
+    Ok(())
+}
+
+/// Writes `FILE_SIZE` bytes to `path` in chunks like those of a download, through a
+/// `BufWriter` of `buffer_size` or, without one, straight to the file.
+async fn write(path: &Path, buffer_size: Option<usize>) -> Result<()> {
+    let chunk = bytes::Bytes::from(vec![0x5a; CHUNK_SIZE]);
+    let file = tokio::fs::File::create(path).await?;
+    let mut file: Box<dyn tokio::io::AsyncWrite + Unpin> = match buffer_size {
+        Some(size) => Box::new(tokio::io::BufWriter::with_capacity(size, file)),
+        None => Box::new(file),
+    };
+    for _ in 0..FILE_SIZE / CHUNK_SIZE {
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await
+}
+
+/// The median time of `RUNS` runs of `run`.
+fn time<F: std::future::Future<Output = Result<()>>>(
+    runtime: &tokio::runtime::Runtime,
+    mut run: impl FnMut() -> F,
+) -> Result<Duration> {
+    let mut times = Vec::new();
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        runtime.block_on(run())?;
+        times.push(start.elapsed());
+    }
+    times.sort();
+    Ok(times[RUNS / 2])
+}
+
+/// Prints how long `what` took, and how much faster that is than `old`.
+fn report(what: &str, elapsed: Duration, old: Duration) {
+    let throughput = FILE_SIZE as f64 / elapsed.as_secs_f64() / (1024.0 * 1024.0);
+    println!(
+        "{:<24} {:>8.1} ms {:>8.0} MiB/s {:>6.2}x",
+        what,
+        elapsed.as_secs_f64() * 1000.0,
+        throughput,
+        old.as_secs_f64() / elapsed.as_secs_f64()
+    );
+}
+
+fn size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{}M", bytes / (1024 * 1024))
+    } else {
+        format!("{}K", bytes / 1024)
+    }
+}
+
+fn main() -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let dir = tempfile::tempdir()?;
+    let source = dir.path().join("source");
+    let dest = dir.path().join("dest");
+    std::fs::write(&source, vec![0xa5; FILE_SIZE])?;
+
+    println!("{} MiB, median of {} runs", FILE_SIZE / (1024 * 1024), RUNS);
+    let mut old = None;
+    for &buffer_size in BUFFER_SIZES {
+        let elapsed = time(&runtime, || hash(&source, buffer_size))?;
+        let old = *old.get_or_insert(elapsed);
+        report(&format!("hash, {} buffer", size(buffer_size)), elapsed, old);
+    }
+    let old = time(&runtime, || write(&dest, None))?;
+    report("write, unbuffered", old, old);
+    for &buffer_size in BUFFER_SIZES {
+        let elapsed = time(&runtime, || write(&dest, Some(buffer_size)))?;
+        report(
+            &format!("write, {} buffer", size(buffer_size)),
+            elapsed,
+            old,
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! SHA-1 and SHA-2 digests backed by ring, pluggable wherever a RustCrypto digest is.
+//!
+//! ring picks its SHA-NI, AVX2 or ARMv8 code at runtime, depending on what the CPU supports,
+//! which makes hashing multi-gigabyte sources several times faster than the portable code.
+
+use digest::generic_array::{typenum, ArrayLength, GenericArray};
+use digest::{FixedOutput, Input, Reset};
+use ring::digest::{Algorithm, Context};
+use std::marker::PhantomData;
+
+#[derive(Clone)]
+pub struct Accelerated<N> {
+    context: Context,
+    size: PhantomData<N>,
+}
+
+pub type Sha1 = Accelerated<typenum::U20>;
+pub type Sha256 = Accelerated<typenum::U32>;
+pub type Sha384 = Accelerated<typenum::U48>;
+pub type Sha512 = Accelerated<typenum::U64>;
+
+impl<N> Accelerated<N> {
+    fn with(algorithm: &'static Algorithm) -> Accelerated<N> {
+        Accelerated {
+            context: Context::new(algorithm),
+            size: PhantomData,
+        }
+    }
+}
+
+impl Sha1 {
+    pub fn new() -> Sha1 {
+        Accelerated::with(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY)
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Accelerated::with(&ring::digest::SHA256)
+    }
+}
+
+impl Sha384 {
+    pub fn new() -> Sha384 {
+        Accelerated::with(&ring::digest::SHA384)
+    }
+}
+
+impl Sha512 {
+    pub fn new() -> Sha512 {
+        Accelerated::with(&ring::digest::SHA512)
+    }
+}
+
+impl<N> Input for Accelerated<N> {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        self.context.update(data.as_ref());
+    }
+}
+
+impl<N: ArrayLength<u8>> FixedOutput for Accelerated<N> {
+    type OutputSize = N;
+
+    fn fixed_result(self) -> GenericArray<u8, N> {
+        GenericArray::clone_from_slice(self.context.finish().as_ref())
+    }
+}
+
+impl<N> Reset for Accelerated<N> {
+    fn reset(&mut self) {
+        self.context = Context::new(self.context.algorithm());
+    }
+}
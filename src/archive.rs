@@ -0,0 +1,125 @@
+//! Integrity checks of downloaded archives, which read them through to the end the way makepkg
+//! would extract them, so a corrupted upstream artifact fails the bump instead of the build.
+
+use crate::logging;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+const TAR_EXTENSIONS: &[&str] = &[
+    ".tar",
+    ".tar.gz",
+    ".tgz",
+    ".tar.bz2",
+    ".tbz2",
+    ".tar.xz",
+    ".txz",
+    ".tar.zst",
+    ".tzst",
+    ".tar.lz",
+    ".tar.lzma",
+];
+
+fn is_tar(filename: &str) -> bool {
+    let filename = filename.to_ascii_lowercase();
+    TAR_EXTENSIONS
+        .iter()
+        .any(|extension| filename.ends_with(extension))
+}
+
+fn is_zip(filename: &str) -> bool {
+    filename.to_ascii_lowercase().ends_with(".zip")
+}
+
+/// Which tool tests an archive, by its file name, or `None` for files that are not archives.
+fn tester(filename: &str) -> Option<&'static [&'static str]> {
+    if is_tar(filename) {
+        return Some(&["bsdtar", "-tf"]);
+    }
+    let filename = filename.to_ascii_lowercase();
+    [
+        (".zip", &["unzip", "-tqq"][..]),
+        (".gz", &["gzip", "-t"]),
+        (".bz2", &["bzip2", "-t"]),
+        (".xz", &["xz", "-t"]),
+        (".lzma", &["xz", "-t"]),
+        (".zst", &["zstd", "-tq"]),
+    ]
+    .iter()
+    .find(|(extension, _)| filename.ends_with(extension))
+    .map(|(_, tester)| *tester)
+}
+
+pub fn is_archive(filename: &str) -> bool {
+    tester(filename).is_some()
+}
+
+/// Whether `filename` is an archive of files, like a tarball, rather than a compressed file.
+pub fn has_files(filename: &str) -> bool {
+    is_tar(filename) || is_zip(filename)
+}
+
+/// Checks that the archive at `path` decompresses and lists cleanly.
+pub fn check(path: &Path, filename: &str) -> Result<()> {
+    let tester = match tester(filename) {
+        Some(tester) => tester,
+        None => return Ok(()),
+    };
+    let output = match run(tester, path, &[]) {
+        // makepkg extracts with bsdtar, GNU tar reads the same archives.
+        Err(e) if e.kind() == ErrorKind::NotFound && tester[0] == "bsdtar" => {
+            run(&["tar", "-tf"], path, &[])?
+        }
+        output => output?,
+    };
+    succeeded(output, || format!("{} is not a valid archive", filename))
+}
+
+/// Extracts the tarball or zip at `path` into `dir`.
+pub fn extract(path: &Path, filename: &str, dir: &Path) -> Result<()> {
+    let output = if is_tar(filename) {
+        let dir = [Path::new("-C"), dir];
+        match run(&["bsdtar", "-xf"], path, &dir) {
+            Err(e) if e.kind() == ErrorKind::NotFound => run(&["tar", "-xf"], path, &dir)?,
+            output => output?,
+        }
+    } else if is_zip(filename) {
+        run(&["unzip", "-qq"], path, &[Path::new("-d"), dir])?
+    } else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not an archive of files", filename),
+        ));
+    };
+    succeeded(output, || format!("cannot extract {}", filename))
+}
+
+fn run(tool: &[&str], path: &Path, extra: &[&Path]) -> Result<Output> {
+    logging::command(
+        Command::new(tool[0])
+            .args(&tool[1..])
+            .arg(path)
+            .args(extra)
+            .stdout(Stdio::null()),
+    )
+    .output()
+}
+
+fn succeeded(output: Output, what: impl FnOnce() -> String) -> Result<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "{}: {}",
+            what(),
+            stderr
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .unwrap_or("unreadable")
+        ),
+    ))
+}
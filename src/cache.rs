@@ -0,0 +1,129 @@
+//! Downloaded sources kept under `$XDG_CACHE_HOME/pkgbump`, so they are not fetched again.
+//!
+//! The files are stored once by the SHA-256 of their content in `objects`, and `urls` maps
+//! the SHA-256 of each URL to the object downloaded from it, along with the server's
+//! validators to ask whether it changed since.
+
+use digest::Digest;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+
+const BUFFER_SIZE: usize = 8 * 1024;
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+/// What identifies a version of a file to its server, for conditional requests.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// The validator for an `If-Range` header, which only takes strong ETags.
+    pub fn if_range(&self) -> Option<&str> {
+        self.etag
+            .as_deref()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    sha256: String,
+    #[serde(flatten)]
+    validators: Validators,
+}
+
+pub struct Cached {
+    pub path: PathBuf,
+    pub validators: Validators,
+}
+
+impl Cache {
+    /// The user's cache, if there is a home directory to keep it in.
+    pub fn new() -> Option<Cache> {
+        let dir = std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(Cache {
+            dir: dir.join("pkgbump"),
+        })
+    }
+
+    /// The cached file downloaded from `url`. One that no longer matches its hash is removed.
+    pub async fn lookup(&self, url: &str) -> Result<Option<Cached>> {
+        let entry: Entry = match tokio::fs::read(self.url_path(url)).await {
+            Ok(entry) => {
+                serde_json::from_slice(&entry).map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let object = self.dir.join("objects").join(&entry.sha256);
+        match sha256_file(&object).await {
+            Ok(actual) if actual == entry.sha256 => Ok(Some(Cached {
+                path: object,
+                validators: entry.validators,
+            })),
+            Ok(_) => {
+                tokio::fs::remove_file(&object).await?;
+                Ok(None)
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Adds the file at `path`, downloaded from `url` with `validators`.
+    pub async fn store(&self, url: &str, path: &Path, validators: Validators) -> Result<()> {
+        let sha256 = sha256_file(path).await?;
+        let objects = self.dir.join("objects");
+        let object = objects.join(&sha256);
+        if tokio::fs::metadata(&object).await.is_err() {
+            tokio::fs::create_dir_all(&objects).await?;
+            // Copied under a temporary name so a concurrent lookup never sees half a file.
+            let temp = objects.join(format!("{}.{}.tmp", sha256, std::process::id()));
+            tokio::fs::copy(path, &temp).await?;
+            tokio::fs::rename(&temp, &object).await?;
+        }
+        let url_path = self.url_path(url);
+        tokio::fs::create_dir_all(url_path.parent().unwrap()).await?;
+        let temp = url_path.with_extension(format!("{}.tmp", std::process::id()));
+        let entry = Entry { sha256, validators };
+        tokio::fs::write(&temp, serde_json::to_vec(&entry)?).await?;
+        tokio::fs::rename(&temp, &url_path).await
+    }
+
+    fn url_path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join("urls")
+            .join(hex::encode(Sha256::digest(url.as_bytes())))
+    }
+}
+
+async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let len = match file.read(&mut buf).await {
+            Ok(0) => return Ok(hex::encode(hasher.result())),
+            Ok(len) => len,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.input(&buf[..len]);
+    }
+}
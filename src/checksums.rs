@@ -0,0 +1,96 @@
+//! Checksum files upstream publishes next to its releases, like `SHA256SUMS` or
+//! `foo.tar.gz.sha256`, in the formats of coreutils (`<sum>  <file>`), BSD
+//! (`SHA256 (<file>) = <sum>`) or a bare sum.
+
+/// A sum from a checksum file, of `file` or of the file the checksum file belongs to.
+#[derive(Debug, Clone)]
+pub struct Published {
+    pub algorithm: Option<String>,
+    pub file: Option<String>,
+    pub sum: String,
+}
+
+pub fn parse(content: &str) -> Vec<Published> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(parse_line)
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<Published> {
+    parse_bsd(line).or_else(|| parse_coreutils(line))
+}
+
+/// A line as `shasum --tag` and `b2sum --tag` write it.
+fn parse_bsd(line: &str) -> Option<Published> {
+    let (name, rest) = line.split_once(" (")?;
+    let (file, sum) = rest.rsplit_once(") = ")?;
+    let algorithm = match name.to_ascii_lowercase().as_str() {
+        "blake2b" => "b2".to_string(),
+        name => name.replace('-', "_"),
+    };
+    Some(Published {
+        algorithm: Some(algorithm),
+        file: Some(file.to_string()),
+        sum: is_hex(sum)?,
+    })
+}
+
+fn parse_coreutils(line: &str) -> Option<Published> {
+    let mut fields = line.splitn(2, char::is_whitespace);
+    let sum = is_hex(fields.next()?)?;
+    let file = fields
+        .next()
+        .map(|file| file.trim_start().trim_start_matches('*').to_string())
+        .filter(|file| !file.is_empty());
+    Some(Published {
+        algorithm: None,
+        file,
+        sum,
+    })
+}
+
+fn is_hex(sum: &str) -> Option<String> {
+    if !sum.is_empty() && sum.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(sum.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+impl Published {
+    /// Whether this is a sum of `algorithm`, like `sum`. Without a known algorithm only the
+    /// length tells, which is ambiguous between SHA-512 and BLAKE2b.
+    pub fn is_of(&self, algorithm: &str, sum: &str) -> bool {
+        match &self.algorithm {
+            Some(name) => name == algorithm,
+            None => self.sum.len() == sum.len() && sum.len() != 128,
+        }
+    }
+}
+
+/// The algorithm the name of a checksum file like `SHA256SUMS` or `foo.tar.gz.b2` suggests.
+pub fn algorithm_of(file: &str) -> Option<&'static str> {
+    let file = file.to_ascii_lowercase();
+    [
+        ("sha512", "sha512"),
+        ("sha384", "sha384"),
+        ("sha256", "sha256"),
+        ("sha224", "sha224"),
+        ("sha1", "sha1"),
+        ("md5", "md5"),
+        ("blake2", "b2"),
+        ("b2", "b2"),
+    ]
+    .iter()
+    .find(|(name, _)| file.contains(name))
+    .map(|(_, algorithm)| *algorithm)
+}
+
+/// The file name at the end of `url`, which is what checksum files list.
+pub fn file_name(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path)
+}
@@ -0,0 +1,70 @@
+//! The POSIX `cksum` CRC, pluggable wherever a RustCrypto digest is.
+//!
+//! The result is the 32-bit CRC in big-endian order; `cksums` arrays hold it in decimal.
+
+use digest::generic_array::{typenum::U4, GenericArray};
+use digest::{FixedOutput, Input, Reset};
+
+const POLYNOMIAL: u32 = 0x04c1_1db7;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ POLYNOMIAL
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+#[derive(Debug, Default, Clone)]
+pub struct Cksum {
+    crc: u32,
+    len: u64,
+}
+
+impl Cksum {
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc = (self.crc << 8) ^ TABLE[((self.crc >> 24) as u8 ^ byte) as usize];
+        }
+    }
+}
+
+impl Input for Cksum {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        let data = data.as_ref();
+        self.update(data);
+        self.len += data.len() as u64;
+    }
+}
+
+impl FixedOutput for Cksum {
+    type OutputSize = U4;
+
+    fn fixed_result(mut self) -> GenericArray<u8, U4> {
+        // The length is fed in little-endian order with its trailing zero bytes left out.
+        let mut len = self.len;
+        while len != 0 {
+            self.update(&[len as u8]);
+            len >>= 8;
+        }
+        GenericArray::clone_from_slice(&(!self.crc).to_be_bytes())
+    }
+}
+
+impl Reset for Cksum {
+    fn reset(&mut self) {
+        *self = Cksum::default();
+    }
+}
@@ -0,0 +1,468 @@
+//! Configuration files.
+//!
+//! Global settings live in `$XDG_CONFIG_HOME/pkgbump/config.toml`, with per-package settings
+//! under `[package.<pkgbase>]`. A `.pkgbump.toml` next to the PKGBUILD holds the same
+//! per-package settings without the section header and takes precedence over the global ones.
+
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+const LOCAL_CONFIG: &str = ".pkgbump.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    package: HashMap<String, PackageConfig>,
+    pub download: DownloadConfig,
+    pub write: WriteConfig,
+    #[serde(skip)]
+    local: PackageConfig,
+}
+
+/// Global download settings, under `[download]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadConfig {
+    /// How many times to retry a request that failed in a way that may be temporary.
+    pub retries: u32,
+    /// Seconds to wait before the first retry, doubling for every further one.
+    pub retry_delay: f64,
+    /// Whether to randomize retry delays by up to half so parallel downloads spread out.
+    pub jitter: bool,
+    /// Seconds to wait for a connection to a server.
+    pub connect_timeout: f64,
+    /// Seconds to wait for more data from a server before giving up on the request.
+    pub read_timeout: f64,
+    /// Proxy for all requests, e.g. `socks5h://localhost:9050`. Without one, the `proxies`
+    /// rules and then the standard `https_proxy` etc. environment variables apply.
+    pub proxy: Option<String>,
+    pub proxies: Vec<ProxyRule>,
+    /// Logins for private sources. Hosts without one fall back to the netrc file.
+    pub credentials: Vec<Credential>,
+    /// A Netscape cookie jar, like curl's `-c` writes, whose cookies are sent along.
+    pub cookie_jar: Option<String>,
+    /// Whether to download every remote source with makepkg's `DLAGENTS` rather than only those
+    /// of protocols other than HTTP(S).
+    pub dlagents: bool,
+    /// `curl` or `aria2c` to hand HTTP(S) transfers to, or `builtin`.
+    pub downloader: Option<String>,
+    /// How sources matching each override are downloaded instead, the first that matches.
+    pub agents: Vec<AgentOverride>,
+    /// Whether to keep downloaded sources in `$XDG_CACHE_HOME/pkgbump` and reuse them.
+    pub cache: bool,
+    pub mirrors: Vec<Mirror>,
+    /// Rewrites applied in order to every source URL before it is downloaded, e.g. to use a
+    /// nearby mirror.
+    rewrites: Vec<Transform>,
+    /// Most bytes per second for all downloads together and for each of them, e.g. `500K`.
+    pub limit_rate: Option<String>,
+    pub limit_rate_per_source: Option<String>,
+    /// Most connections to download a large source over in parallel ranges.
+    pub segments: u32,
+    /// Whether to check that all sources exist with HEAD requests before downloading any.
+    pub preflight: bool,
+    /// Bytes to read and write files in at a time, e.g. `1M`.
+    pub buffer_size: Option<String>,
+    /// Most threads to spread the algorithms of each download over, one per algorithm by
+    /// default. One algorithm is never split over several threads.
+    pub hash_threads: Option<usize>,
+    /// Whether to write downloaded sources to disk, rather than only hashing them.
+    pub keep_sources: bool,
+    /// Most redirects to follow for one request.
+    pub max_redirects: usize,
+    /// The `User-Agent` of all requests, `pkgbump/<version>` by default.
+    pub user_agent: Option<String>,
+    /// Whether to refuse downloading sources over plain HTTP or FTP, except from `http_hosts`
+    /// and their subdomains.
+    pub require_https: bool,
+    pub http_hosts: Vec<String>,
+    /// Hosts to only download from, all of them if empty, and ones never to download from,
+    /// each with their subdomains.
+    pub allowed_hosts: Vec<String>,
+    pub blocked_hosts: Vec<String>,
+    pub pins: Vec<Pin>,
+    /// 4 or 6 to only connect to servers over that IP version.
+    pub ip_version: Option<u8>,
+    /// Whether to use HTTP/2 with servers that offer it, rather than only HTTP/1.1.
+    pub http2: bool,
+    /// Most bytes a source may have, e.g. `2G`, so a wrong URL cannot fill the disk.
+    pub max_size: Option<String>,
+    pub size_limits: Vec<SizeLimit>,
+}
+
+/// Other places to download sources under `url` from when it fails, each holding the same
+/// files under the same paths.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mirror {
+    pub url: String,
+    pub mirrors: Vec<String>,
+}
+
+/// How to download sources whose URL matches `pattern`: with `downloader`, `builtin`, `curl` or
+/// `aria2c`, or with `command`, a command line like those of `DLAGENTS`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentOverride {
+    pub pattern: String,
+    pub downloader: Option<String>,
+    pub command: Option<String>,
+}
+
+/// The most bytes of sources under `url`, instead of `max_size`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SizeLimit {
+    pub url: String,
+    pub max_size: String,
+}
+
+/// The TLS certificates `host` and its subdomains may present, by the SHA-256 in hex of the
+/// whole certificate or of its public key (SPKI). Connections to them fail with any other
+/// certificate before a request is sent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pin {
+    pub host: String,
+    #[serde(default)]
+    pub certificates: Vec<String>,
+    #[serde(default)]
+    pub public_keys: Vec<String>,
+}
+
+/// Authentication for requests to `host` and its subdomains: a bearer `token`, basic auth with
+/// `username` and `password`, and any extra `headers` and `cookies`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Credential {
+    pub host: String,
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub cookies: BTreeMap<String, String>,
+}
+
+/// Routes requests to `host` and its subdomains through `proxy`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyRule {
+    pub host: String,
+    pub proxy: String,
+}
+
+/// How `--write` replaces the PKGBUILD, under `[write]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WriteConfig {
+    /// Whether to keep a copy of the PKGBUILD being replaced.
+    pub backup: bool,
+    /// Where to keep it, relative to the package directory.
+    pub backup_file: String,
+    /// A directory to keep every replaced PKGBUILD in instead, each named after its version.
+    pub backup_dir: Option<String>,
+    /// Whether to update `.SRCINFO` along with the PKGBUILD, and stage it in git.
+    pub srcinfo: bool,
+}
+
+impl Default for WriteConfig {
+    fn default() -> WriteConfig {
+        WriteConfig {
+            backup: true,
+            backup_file: "PKGBUILD.pkgbump.bak".to_string(),
+            backup_dir: None,
+            srcinfo: true,
+        }
+    }
+}
+
+impl Default for DownloadConfig {
+    fn default() -> DownloadConfig {
+        DownloadConfig {
+            retries: 3,
+            retry_delay: 1.0,
+            jitter: true,
+            connect_timeout: 30.0,
+            read_timeout: 60.0,
+            proxy: None,
+            proxies: Vec::new(),
+            credentials: Vec::new(),
+            cookie_jar: None,
+            dlagents: false,
+            downloader: None,
+            agents: Vec::new(),
+            cache: true,
+            mirrors: Vec::new(),
+            rewrites: Vec::new(),
+            limit_rate: None,
+            limit_rate_per_source: None,
+            segments: 1,
+            preflight: true,
+            buffer_size: None,
+            hash_threads: None,
+            keep_sources: true,
+            max_redirects: 10,
+            user_agent: None,
+            require_https: false,
+            http_hosts: Vec::new(),
+            allowed_hosts: Vec::new(),
+            blocked_hosts: Vec::new(),
+            pins: Vec::new(),
+            ip_version: None,
+            http2: true,
+            max_size: None,
+            size_limits: Vec::new(),
+        }
+    }
+}
+
+impl DownloadConfig {
+    pub fn url_rewrites(&self) -> Result<Vec<UrlRewrite>> {
+        self.rewrites
+            .iter()
+            .map(|rewrite| {
+                Ok(UrlRewrite {
+                    regex: Regex::new(&rewrite.pattern).map_err(|e| invalid(e.to_string()))?,
+                    replace: rewrite.replace.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PackageConfig {
+    /// Helper variables rewritten from the new version, e.g. `_tag = "v{version}"`.
+    pub variables: BTreeMap<String, VariableRule>,
+    /// Rewrites applied in order to the upstream version before the built-in normalization.
+    transforms: Vec<Transform>,
+    /// Rewrites of source URLs to try, in order, when a source is not found.
+    url_candidates: Vec<Transform>,
+    /// URLs of checksum files upstream publishes, with the same placeholders as variables, or
+    /// `auto` to look for them next to each source.
+    pub checksums: Vec<String>,
+    /// minisign or signify public keys the signatures of sources must be made with.
+    pub signing_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Transform {
+    pattern: String,
+    replace: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum VariableRule {
+    Template(String),
+    Pattern { pattern: String, template: String },
+}
+
+impl Config {
+    pub fn load() -> Result<Config> {
+        let mut config: Config = match config_dir() {
+            Some(dir) => read(&dir.join("pkgbump").join("config.toml"))?.unwrap_or_default(),
+            None => Config::default(),
+        };
+        config.local = read(Path::new(LOCAL_CONFIG))?.unwrap_or_default();
+        Ok(config)
+    }
+
+    pub fn package(&self, pkgbase: &str) -> PackageConfig {
+        let mut package = self.package.get(pkgbase).cloned().unwrap_or_default();
+        package.variables.extend(self.local.variables.clone());
+        package.transforms.extend(self.local.transforms.clone());
+        package
+            .url_candidates
+            .extend(self.local.url_candidates.clone());
+        package.checksums.extend(self.local.checksums.clone());
+        package.signing_keys.extend(self.local.signing_keys.clone());
+        package
+    }
+}
+
+impl PackageConfig {
+    pub fn transform_version(&self, version: &str) -> Result<String> {
+        let mut version = version.to_string();
+        for transform in &self.transforms {
+            let regex = Regex::new(&transform.pattern).map_err(|e| invalid(e.to_string()))?;
+            let transformed = regex
+                .replace_all(&version, transform.replace.as_str())
+                .into_owned();
+            debug!(
+                "transform `{}` -> `{}`: {} -> {}",
+                transform.pattern, transform.replace, version, transformed
+            );
+            version = transformed;
+        }
+        Ok(version)
+    }
+
+    /// The rewrites to try on the URL of a source that is not found, with `{version}` etc.
+    /// expanded for `version`. The configured ones come first, then swaps of the archive
+    /// format.
+    pub fn url_rewrites(&self, version: &str) -> Result<Vec<UrlRewrite>> {
+        let mut rewrites = Vec::new();
+        for candidate in &self.url_candidates {
+            rewrites.push(UrlRewrite {
+                regex: Regex::new(&candidate.pattern).map_err(|e| invalid(e.to_string()))?,
+                replace: VariableRule::Template(candidate.replace.clone()).render(version)?,
+            });
+        }
+        let archive = Regex::new(r"\.(tar\.(gz|xz|bz2|zst)|tgz|zip)$").unwrap();
+        for extension in ARCHIVE_EXTENSIONS {
+            rewrites.push(UrlRewrite {
+                regex: archive.clone(),
+                replace: extension.to_string(),
+            });
+        }
+        Ok(rewrites)
+    }
+}
+
+const ARCHIVE_EXTENSIONS: &[&str] = &[".tar.xz", ".tar.gz", ".tar.zst", ".tar.bz2", ".zip"];
+
+/// A candidate location for a source that moved, or where to download it from instead.
+pub struct UrlRewrite {
+    regex: Regex,
+    replace: String,
+}
+
+impl UrlRewrite {
+    /// Returns the rewritten URL, or `None` if the rewrite does not change it.
+    pub fn apply(&self, url: &str) -> Option<String> {
+        let rewritten = self.regex.replace_all(url, self.replace.as_str());
+        if rewritten == url {
+            None
+        } else {
+            debug!(
+                "rewrite `{}` -> `{}`: {} -> {}",
+                self.regex, self.replace, url, rewritten
+            );
+            Some(rewritten.into_owned())
+        }
+    }
+}
+
+impl VariableRule {
+    /// Derives the variable's value from `version`. Templates may use `{version}`, `{major}`,
+    /// `{minor}` and `{patch}`; pattern rules may also use the regex's numbered and named groups.
+    pub fn render(&self, version: &str) -> Result<String> {
+        let components: Vec<&str> = version.split('.').collect();
+        let builtin = |name: &str| -> Option<String> {
+            let component = |i: usize| components.get(i).map(|c| c.to_string());
+            match name {
+                "version" => Some(version.to_string()),
+                "major" => component(0),
+                "minor" => component(1),
+                "patch" => component(2),
+                _ => None,
+            }
+        };
+        match self {
+            VariableRule::Template(template) => expand(template, builtin),
+            VariableRule::Pattern { pattern, template } => {
+                let regex = Regex::new(pattern).map_err(|e| invalid(e.to_string()))?;
+                let captures = regex.captures(version).ok_or_else(|| {
+                    invalid(format!("version {} does not match `{}`", version, pattern))
+                })?;
+                expand(template, |name| {
+                    let group = match name.parse::<usize>() {
+                        Ok(i) => captures.get(i),
+                        Err(_) => captures.name(name),
+                    };
+                    group
+                        .map(|group| group.as_str().to_string())
+                        .or_else(|| builtin(name))
+                })
+            }
+        }
+    }
+}
+
+/// Expands the `{name}` placeholders of `template`. `${name}` is left alone, as it is a group
+/// of the pattern in URL candidates.
+fn expand(template: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut missing = None;
+    let expanded = Regex::new(r"(\$?)\{(\w+)\}")
+        .unwrap()
+        .replace_all(template, |caps: &Captures| {
+            if !caps[1].is_empty() {
+                return caps[0].to_string();
+            }
+            lookup(&caps[2]).unwrap_or_else(|| {
+                missing.get_or_insert_with(|| caps[2].to_string());
+                String::new()
+            })
+        })
+        .into_owned();
+    match missing {
+        Some(name) => Err(invalid(format!(
+            "unknown placeholder `{{{}}}` in `{}`",
+            name, template
+        ))),
+        None => Ok(expanded),
+    }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+fn read<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<Option<T>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    toml::from_str(&content)
+        .map(Some)
+        .map_err(|e| invalid(format!("{}: {}", path.display(), e)))
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_rewrites_keep_group_references() {
+        let package: PackageConfig = toml::from_str(
+            r#"
+            [[url_candidates]]
+            pattern = '^(?P<base>https://example\.org/.*)/\d+\.\d+/(.*)$'
+            replace = '${base}/{major}.{minor}/${2}'
+            "#,
+        )
+        .unwrap();
+        let rewrites = package.url_rewrites("2.1.3").unwrap();
+        assert_eq!(
+            rewrites[0]
+                .apply("https://example.org/pub/foo/2.0/foo-2.1.3.tar.gz")
+                .unwrap(),
+            "https://example.org/pub/foo/2.1/foo-2.1.3.tar.gz"
+        );
+    }
+
+    #[test]
+    fn templates_reject_unknown_placeholders() {
+        let rule = VariableRule::Template("{version}-{nope}".to_string());
+        assert!(rule.render("1.0").is_err());
+        let rule = VariableRule::Template("{version}-${nope}".to_string());
+        assert_eq!(rule.render("1.0").unwrap(), "1.0-${nope}");
+    }
+}
@@ -0,0 +1,80 @@
+//! Cookies from a Netscape cookie jar, the format curl's `-c` writes and browser extensions
+//! export, for sources behind a license click-through or a login.
+
+use std::io::{Error, Result};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The prefix curl marks `HttpOnly` cookies with, on what would otherwise be a comment.
+const HTTP_ONLY: &str = "#HttpOnly_";
+
+#[derive(Debug, Clone)]
+pub struct Cookie {
+    domain: String,
+    subdomains: bool,
+    path: String,
+    secure: bool,
+    /// Seconds since the epoch the cookie expires at, 0 for session cookies that never do here.
+    expires: u64,
+    pub name: String,
+    pub value: String,
+}
+
+impl Cookie {
+    /// Whether the cookie is sent with requests to `url`.
+    pub fn matches(&self, url: &reqwest::Url) -> bool {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let domain_matches = host == self.domain
+            || self.subdomains
+                && host
+                    .strip_suffix(&self.domain)
+                    .is_some_and(|sub| sub.ends_with('.'));
+        let path = url.path();
+        let path_matches = path == self.path
+            || path.starts_with(&self.path)
+                && (self.path.ends_with('/') || path[self.path.len()..].starts_with('/'));
+        domain_matches
+            && path_matches
+            && (!self.secure || url.scheme() == "https")
+            && (self.expires == 0 || self.expires > now())
+    }
+}
+
+/// Reads the cookies in the jar at `path`.
+pub fn load(path: &Path) -> Result<Vec<Cookie>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    Ok(parse(&content))
+}
+
+fn parse(content: &str) -> Vec<Cookie> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let line = line.strip_prefix(HTTP_ONLY).unwrap_or(line);
+            if line.starts_with('#') {
+                return None;
+            }
+            let fields: Vec<_> = line.trim_end_matches('\r').split('\t').collect();
+            if let [domain, subdomains, path, secure, expires, name, value] = fields.as_slice() {
+                Some(Cookie {
+                    domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+                    subdomains: subdomains.eq_ignore_ascii_case("TRUE"),
+                    path: path.to_string(),
+                    secure: secure.eq_ignore_ascii_case("TRUE"),
+                    expires: expires.parse().unwrap_or(0),
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
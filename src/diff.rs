@@ -0,0 +1,157 @@
+//! Unified diffs of the edited PKGBUILD, as `git diff` prints them, to review a bump at a glance
+//! or paste it into a commit message.
+
+use std::ops::Range;
+
+/// Unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// The unified diff from `old` to `new`, both of the file `name`, empty if they are the same.
+/// Lines are compared as bytes, so that line endings and non-UTF-8 text show up as they are.
+pub fn unified(old: &[u8], new: &[u8], name: &str, color: bool) -> Vec<u8> {
+    let edits = edits(&lines(old), &lines(new));
+    let hunks = hunks(&edits);
+    let mut diff = Vec::new();
+    if hunks.is_empty() {
+        return diff;
+    }
+    let paint = |diff: &mut Vec<u8>, style: &str, text: &[u8]| {
+        if color {
+            diff.extend_from_slice(style.as_bytes());
+            diff.extend_from_slice(text);
+            diff.extend_from_slice(RESET.as_bytes());
+        } else {
+            diff.extend_from_slice(text);
+        }
+        diff.push(b'\n');
+    };
+    paint(&mut diff, BOLD, format!("--- a/{}", name).as_bytes());
+    paint(&mut diff, BOLD, format!("+++ b/{}", name).as_bytes());
+    for hunk in hunks {
+        let before = &edits[..hunk.start];
+        let within = &edits[hunk.clone()];
+        let count =
+            |edits: &[(Op, &[u8])], skip: Op| edits.iter().filter(|(op, _)| *op != skip).count();
+        let header = format!(
+            "@@ -{} +{} @@",
+            range(count(before, Op::Insert), count(within, Op::Insert)),
+            range(count(before, Op::Delete), count(within, Op::Delete))
+        );
+        paint(&mut diff, CYAN, header.as_bytes());
+        for (op, line) in within {
+            let (prefix, style) = match op {
+                Op::Equal => (b' ', ""),
+                Op::Delete => (b'-', RED),
+                Op::Insert => (b'+', GREEN),
+            };
+            let text = line.strip_suffix(b"\n").unwrap_or(line);
+            let text = [&[prefix][..], text].concat();
+            if *op == Op::Equal {
+                diff.extend_from_slice(&text);
+                diff.push(b'\n');
+            } else {
+                paint(&mut diff, style, &text);
+            }
+            if !line.ends_with(b"\n") {
+                diff.extend_from_slice(b"\\ No newline at end of file\n");
+            }
+        }
+    }
+    diff
+}
+
+/// The lines of `content`, each with its line ending.
+fn lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// A shortest way to turn `old` into `new`, line by line, from their longest common subsequence.
+fn edits<'a>(old: &[&'a [u8]], new: &[&'a [u8]]) -> Vec<(Op, &'a [u8])> {
+    // Only the lines between the common start and end need the quadratic table.
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    // `common[i][j]` is the length of the longest common subsequence of `a[i..]` and `b[j..]`.
+    let mut common = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            common[i][j] = if a[i] == b[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let mut edits: Vec<_> = old[..prefix]
+        .iter()
+        .map(|line| (Op::Equal, *line))
+        .collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            edits.push((Op::Equal, a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || i < a.len() && common[i + 1][j] >= common[i][j + 1] {
+            edits.push((Op::Delete, a[i]));
+            i += 1;
+        } else {
+            edits.push((Op::Insert, b[j]));
+            j += 1;
+        }
+    }
+    edits.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| (Op::Equal, *line)),
+    );
+    edits
+}
+
+/// The ranges of `edits` shown together, the changes with their context. Changes closer than
+/// twice the context share a hunk.
+fn hunks(edits: &[(Op, &[u8])]) -> Vec<Range<usize>> {
+    let mut hunks: Vec<Range<usize>> = Vec::new();
+    for (i, _) in edits
+        .iter()
+        .enumerate()
+        .filter(|(_, (op, _))| *op != Op::Equal)
+    {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + 1 + CONTEXT).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+    hunks
+}
+
+/// A hunk header's `start,count` of `count` lines after the first `before`. An empty range
+/// starts at the line before it, as `diff -u` has it.
+fn range(before: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", before),
+        1 => (before + 1).to_string(),
+        _ => format!("{},{}", before + 1, count),
+    }
+}
@@ -0,0 +1,2141 @@
+//! Downloading and hashing sources, concurrently on an async runtime with one shared client.
+
+use crate::accel::{Sha1, Sha256, Sha384, Sha512};
+use crate::cache::{Cache, Cached, Validators};
+use crate::cksum::Cksum;
+use crate::config::{Credential, DownloadConfig, Mirror, UrlRewrite};
+use crate::cookies::{self, Cookie};
+use crate::makepkg::MakepkgConf;
+use crate::netrc::{self, Login};
+use crate::pin;
+use crate::progress::{self, Progress};
+use crate::Source;
+use blake2::Blake2b;
+use digest::{Digest, DynDigest};
+use futures::future;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use indicatif::HumanBytes;
+use md5::Md5;
+use regex::Regex;
+use sha2::Sha224;
+use sha3::{Sha3_256, Sha3_512};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::{debug, info, warn};
+
+/// Sum of sources that were not downloaded, for updpkgsums or a later bump to replace.
+const PLACEHOLDER_SUM: &str = "RUN-UPDPKGSUMS";
+
+const BUFFER_SIZE: usize = 8 * 1024;
+const USER_AGENT: &str = concat!("pkgbump/", env!("CARGO_PKG_VERSION"));
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// Free space to leave on top of the sources' sizes, a tenth of them but at least this much.
+const MIN_HEADROOM: u64 = 16 * 1024 * 1024;
+
+/// Smallest part of a source worth its own connection in a segmented download.
+const MIN_SEGMENT_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Protocols downloaded natively unless told to use makepkg's agents for everything.
+const NATIVE_PROTOCOLS: &[&str] = &["file", "http", "https"];
+
+/// makepkg's own agents for protocols reqwest does not speak, for when makepkg.conf has none.
+const DEFAULT_AGENTS: &[(&str, &str)] = &[
+    (
+        "ftp",
+        "curl -qgfC - --ftp-pasv --retry 3 --retry-delay 3 -o %o %u",
+    ),
+    ("scp", "scp -C %u %o"),
+];
+
+/// Tools HTTP(S) transfers can be handed to instead of the builtin client, as agents.
+const EXTERNAL_DOWNLOADERS: &[(&str, &str)] = &[
+    // Quiet, since their progress of concurrent downloads would be interleaved.
+    (
+        "curl",
+        "curl -qgsSfLR -C - --retry 3 --retry-delay 3 -o %o %u",
+    ),
+    (
+        "aria2c",
+        "aria2c -UWget -s4 -x4 -c -R --auto-file-renaming=false --summary-interval=0 \
+         --console-log-level=error --download-result=hide -d / -o %o %u",
+    ),
+];
+
+/// Longest the doubling wait between retries grows to, unless the first one is longer.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+
+/// Chunks of a download waiting to be hashed, at most.
+const HASH_QUEUE: usize = 64;
+
+type BoxedDigest = Box<dyn DynDigest + Send>;
+type Digests = Vec<BoxedDigest>;
+
+/// Sources found by one of the URL rewrites, by index.
+pub type Moved<'a> = Vec<(usize, &'a UrlRewrite)>;
+
+/// Sources that failed to download while keeping going, by index.
+pub type Failed = Vec<(usize, Error)>;
+
+/// How failed requests are retried: up to `retries` times, after `delay` doubling every time and
+/// randomized by up to half with `jitter`.
+#[derive(Debug, Clone)]
+struct Retry {
+    retries: u32,
+    delay: Duration,
+    jitter: bool,
+}
+
+impl Retry {
+    /// The wait before retry number `attempt`, counting from 1.
+    fn delay(&self, attempt: u32) -> Duration {
+        let max = MAX_RETRY_DELAY.max(self.delay);
+        let delay = self
+            .delay
+            .checked_mul(2u32.saturating_pow(attempt - 1))
+            .map_or(max, |delay| delay.min(max));
+        if self.jitter {
+            let random = RandomState::new().build_hasher().finish();
+            delay.mul_f64(1.0 - (random % 1000) as f64 / 2000.0)
+        } else {
+            delay
+        }
+    }
+}
+
+/// Keeps a transfer at `rate` bytes per second on average from its first byte on.
+#[derive(Debug)]
+struct RateLimit {
+    rate: u64,
+    started: Option<Instant>,
+    bytes: u64,
+}
+
+impl RateLimit {
+    fn new(rate: u64) -> RateLimit {
+        RateLimit {
+            rate,
+            started: None,
+            bytes: 0,
+        }
+    }
+
+    /// How long to pause after transferring `len` more bytes.
+    fn delay(&mut self, len: usize) -> Duration {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        self.bytes += len as u64;
+        Duration::from_secs_f64(self.bytes as f64 / self.rate as f64)
+            .saturating_sub(started.elapsed())
+    }
+}
+
+/// A download split into ranges of `source` that are written to `part` concurrently.
+struct Segments<'a> {
+    source: &'a Source,
+    url: String,
+    part: &'a Path,
+    progress: Mutex<&'a mut progress::Download>,
+    limit: Mutex<Option<RateLimit>>,
+}
+
+/// A file being written that is removed again unless it is finished, also if its download is
+/// cancelled.
+struct Partial<'a> {
+    path: &'a Path,
+    finished: bool,
+}
+
+impl<'a> Partial<'a> {
+    fn new(path: &'a Path) -> Partial<'a> {
+        Partial {
+            path,
+            finished: false,
+        }
+    }
+
+    fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for Partial<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = std::fs::remove_file(self.path);
+        }
+    }
+}
+
+/// Where sources may be downloaded from, as domains that include their subdomains.
+struct HostPolicy {
+    allowed_hosts: Vec<String>,
+    blocked_hosts: Vec<String>,
+    require_https: bool,
+    http_hosts: Vec<String>,
+    /// Hosts with pinned certificates, which only the builtin client can check.
+    pinned_hosts: Vec<String>,
+}
+
+impl HostPolicy {
+    fn new(config: &DownloadConfig) -> HostPolicy {
+        let lowercase =
+            |hosts: &[String]| hosts.iter().map(|host| host.to_ascii_lowercase()).collect();
+        HostPolicy {
+            allowed_hosts: lowercase(&config.allowed_hosts),
+            blocked_hosts: lowercase(&config.blocked_hosts),
+            require_https: config.require_https,
+            http_hosts: lowercase(&config.http_hosts),
+            pinned_hosts: config
+                .pins
+                .iter()
+                .map(|pin| pin.host.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Whether the host of `url` has pinned certificates.
+    fn pinned(&self, url: &str) -> bool {
+        reqwest::Url::parse(url).is_ok_and(|url| {
+            let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+            self.pinned_hosts
+                .iter()
+                .any(|domain| host_matches(&host, domain))
+        })
+    }
+
+    /// Why `url` may not be downloaded from, if it may not.
+    fn refusal(&self, url: &str) -> Option<&'static str> {
+        let url = reqwest::Url::parse(url).ok()?;
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        // VCS URLs like `git+http://` are plain text as well.
+        let scheme = url.scheme().rsplit('+').next().unwrap_or_default();
+        let listed = |domains: &[String]| domains.iter().any(|domain| host_matches(&host, domain));
+        if listed(&self.blocked_hosts) {
+            Some("blocked host")
+        } else if !self.allowed_hosts.is_empty() && !listed(&self.allowed_hosts) {
+            Some("host not allowed")
+        } else if self.require_https
+            && ["http", "ftp", "git", "svn", "hkp"].contains(&scheme)
+            && !listed(&self.http_hosts)
+        {
+            Some("HTTPS required")
+        } else if url.scheme() != "https" && self.pinned(url.as_str()) {
+            // Plain text has no certificate, and git or gpg would not check the pins.
+            Some("pinned host only allowed over builtin HTTPS")
+        } else {
+            None
+        }
+    }
+}
+
+/// Hashes the chunks of a download on threads of their own, so the transfer does not wait for
+/// the hashing. Each digest gets a thread unless `threads` limits them, when they are split
+/// evenly among that many.
+struct Hashing {
+    workers: Vec<(
+        tokio::sync::mpsc::Sender<bytes::Bytes>,
+        tokio::task::JoinHandle<Digests>,
+    )>,
+}
+
+impl Hashing {
+    fn start(digests: Digests, threads: Option<usize>) -> Hashing {
+        let threads = threads.unwrap_or(digests.len()).max(1);
+        let per_thread = digests.len().div_ceil(threads);
+        let mut digests = digests.into_iter().peekable();
+        let mut workers = Vec::new();
+        while digests.peek().is_some() {
+            let mut group: Digests = digests.by_ref().take(per_thread).collect();
+            let (chunks, mut receiver) = tokio::sync::mpsc::channel::<bytes::Bytes>(HASH_QUEUE);
+            let worker = tokio::task::spawn_blocking(move || {
+                while let Some(chunk) = receiver.blocking_recv() {
+                    for digest in &mut group {
+                        digest.input(&chunk);
+                    }
+                }
+                group
+            });
+            workers.push((chunks, worker));
+        }
+        Hashing { workers }
+    }
+
+    async fn input(&self, chunk: bytes::Bytes) {
+        for (chunks, _) in &self.workers {
+            // Only fails if the thread panicked, which `finish` reports.
+            let _ = chunks.send(chunk.clone()).await;
+        }
+    }
+
+    async fn finish(self) -> Result<Digests> {
+        let mut digests = Digests::new();
+        for (chunks, worker) in self.workers {
+            drop(chunks);
+            digests.extend(worker.await.map_err(Error::other)?);
+        }
+        Ok(digests)
+    }
+}
+
+/// A network failure that may go away on its own.
+#[derive(Debug)]
+struct Transient(String);
+
+impl fmt::Display for Transient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Transient {}
+
+pub struct Downloader {
+    runtime: tokio::runtime::Runtime,
+    client: reqwest::Client,
+    progress: Progress,
+    retry: Retry,
+    credentials: Vec<Credential>,
+    logins: Vec<Login>,
+    /// The cookies of the configured cookie jar.
+    cookies: Vec<Cookie>,
+    /// makepkg's `DLAGENTS` as protocol and command line.
+    agents: Vec<(String, String)>,
+    all_agents: bool,
+    /// The agent of the external downloader for HTTP(S) sources, if one is used.
+    external: Option<&'static str>,
+    /// The agents of sources whose URLs match, or `None` for the builtin client.
+    overrides: Vec<(Regex, Option<String>)>,
+    /// makepkg's shared source directory, if not the current one.
+    srcdest: Option<PathBuf>,
+    cache: Option<Cache>,
+    /// Whether files already downloaded can be used instead of downloading them again.
+    reuse: bool,
+    mirrors: Vec<Mirror>,
+    /// Rewrites of the URLs to download from.
+    url_rewrites: Vec<UrlRewrite>,
+    limit_rate: Option<Mutex<RateLimit>>,
+    limit_rate_per_source: Option<u64>,
+    /// Most connections to download one source over.
+    segments: u64,
+    preflight: bool,
+    /// Bytes to read files and write downloads in at a time.
+    buffer_size: usize,
+    hash_threads: Option<usize>,
+    /// The sums of the sources downloaded so far, by `hashed_key`.
+    hashed: Mutex<HashMap<String, Vec<String>>>,
+    /// Whether downloaded sources are written to disk, rather than only hashed.
+    keep: bool,
+    /// Whether to download the other sources when one fails, rather than stop.
+    keep_going: bool,
+    offline: bool,
+    /// Whether to only check that sources exist, without downloading or hashing any.
+    dry_run: bool,
+    hosts: HostPolicy,
+    max_size: Option<u64>,
+    /// Overrides of `max_size` for sources under a URL prefix.
+    size_limits: Vec<(String, u64)>,
+}
+
+impl Downloader {
+    pub fn new(config: &DownloadConfig, makepkg: &MakepkgConf) -> Result<Downloader> {
+        let mut client = reqwest::Client::builder()
+            .connect_timeout(seconds(config.connect_timeout, "connect_timeout")?)
+            .read_timeout(seconds(config.read_timeout, "read_timeout")?)
+            .redirect(redirect_policy(config))
+            .user_agent(config.user_agent.as_deref().unwrap_or(USER_AGENT))
+            // Connections stay open between the sources, preflight and checksum requests to the
+            // same host, so each pays for its TLS handshake once.
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .tcp_keepalive(TCP_KEEPALIVE)
+            .http2_adaptive_window(true);
+        if !config.http2 {
+            client = client.http1_only();
+        }
+        if !config.pins.is_empty() {
+            client = client.use_preconfigured_tls(pin::tls_config(&config.pins, config.http2)?);
+        }
+        for proxy in proxies(config)? {
+            client = client.proxy(proxy);
+        }
+        match config.ip_version {
+            None => {}
+            Some(version @ 4) | Some(version @ 6) => {
+                client = client.dns_resolver(std::sync::Arc::new(IpVersion(version)));
+            }
+            Some(version) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("ip_version is {}, but must be 4 or 6", version),
+                ))
+            }
+        }
+        Ok(Downloader {
+            runtime: tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?,
+            client: client.build().map_err(Error::other)?,
+            progress: Progress::new(),
+            retry: Retry {
+                retries: config.retries,
+                delay: seconds(config.retry_delay, "retry_delay")?,
+                jitter: config.jitter,
+            },
+            credentials: config.credentials.clone(),
+            logins: netrc::load()?,
+            cookies: match &config.cookie_jar {
+                Some(path) => cookies::load(Path::new(path))?,
+                None => Vec::new(),
+            },
+            agents: makepkg
+                .array("DLAGENTS")?
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|agent| agent.split_once("::"))
+                .map(|(protocol, command)| (protocol.to_string(), command.to_string()))
+                .collect(),
+            all_agents: config.dlagents,
+            external: match &config.downloader {
+                Some(name) => external_downloader(name)?,
+                None => None,
+            },
+            overrides: config
+                .agents
+                .iter()
+                .map(|agent| {
+                    let regex = Regex::new(&agent.pattern)
+                        .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+                    let command = match (&agent.downloader, &agent.command) {
+                        (Some(name), None) => external_downloader(name)?.map(str::to_string),
+                        (None, Some(command)) => Some(command.clone()),
+                        _ => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                format!(
+                                    "the agent for `{}` needs either a downloader or a command",
+                                    agent.pattern
+                                ),
+                            ))
+                        }
+                    };
+                    Ok((regex, command))
+                })
+                .collect::<Result<_>>()?,
+            srcdest: makepkg.srcdest()?,
+            cache: if config.cache { Cache::new() } else { None },
+            reuse: true,
+            mirrors: config.mirrors.clone(),
+            url_rewrites: config.url_rewrites()?,
+            limit_rate: match &config.limit_rate {
+                Some(rate) => Some(Mutex::new(RateLimit::new(parse_size(rate, "limit_rate")?))),
+                None => None,
+            },
+            limit_rate_per_source: match &config.limit_rate_per_source {
+                Some(rate) => Some(parse_size(rate, "limit_rate_per_source")?),
+                None => None,
+            },
+            segments: config.segments.max(1).into(),
+            preflight: config.preflight,
+            buffer_size: match &config.buffer_size {
+                Some(size) => parse_size(size, "buffer_size")? as usize,
+                None => BUFFER_SIZE,
+            },
+            hash_threads: config.hash_threads,
+            hashed: Mutex::new(HashMap::new()),
+            keep: config.keep_sources,
+            keep_going: false,
+            offline: false,
+            dry_run: false,
+            hosts: HostPolicy::new(config),
+            max_size: match &config.max_size {
+                Some(size) => Some(parse_size(size, "max_size")?),
+                None => None,
+            },
+            size_limits: config
+                .size_limits
+                .iter()
+                .map(|limit| Ok((limit.url.clone(), parse_size(&limit.max_size, "max_size")?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// How long the rate limits require to wait after a download of `limit` got `len` bytes.
+    fn throttle(&self, limit: &mut Option<RateLimit>, len: usize) -> Duration {
+        let delay = limit
+            .as_mut()
+            .map_or(Duration::ZERO, |limit| limit.delay(len));
+        match &self.limit_rate {
+            Some(global) => delay.max(global.lock().unwrap().delay(len)),
+            None => delay,
+        }
+    }
+
+    /// The URL to download `url` from.
+    fn rewrite(&self, url: &str) -> String {
+        let mut url = url.to_string();
+        for rewrite in &self.url_rewrites {
+            if let Some(rewritten) = rewrite.apply(&url) {
+                url = rewritten;
+            }
+        }
+        url
+    }
+
+    /// The URLs of `url` on the mirrors of the first prefix it has.
+    fn mirror_urls(&self, url: &str) -> Vec<String> {
+        self.mirrors
+            .iter()
+            .find_map(|mirror| {
+                let path = url.strip_prefix(&mirror.url)?;
+                Some(
+                    mirror
+                        .mirrors
+                        .iter()
+                        .map(|mirror| format!("{}{}", mirror, path))
+                        .filter(|url| !self.refuses(url))
+                        .collect(),
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether `url` is not to be downloaded from, because of its host or its lack of HTTPS.
+    fn refuses(&self, url: &str) -> bool {
+        self.hosts.refusal(url).is_some()
+    }
+
+    /// Fails if `url` is not to be connected to, for anything else than the sources too, like
+    /// keyservers and git repositories.
+    pub fn check_host(&self, url: &str) -> Result<()> {
+        match self.hosts.refusal(url) {
+            Some(reason) => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("refusing to connect to {} ({})", url, reason),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Downloads every source anew instead of using the files already in SRCDEST, the current
+    /// directory or the cache.
+    pub fn redownload(&mut self) {
+        self.reuse = false;
+    }
+
+    /// Downloads the other sources when one fails, instead of cancelling them.
+    pub fn keep_going(&mut self) {
+        self.keep_going = true;
+    }
+
+    /// Takes sources only from disk and the cache, failing those that would be downloaded, and
+    /// keeps going so all of them are reported.
+    pub fn offline(&mut self) {
+        self.offline = true;
+        self.keep_going = true;
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Neither downloads nor hashes sources, so their sums are placeholders unless the source is
+    /// unchanged. The preflight still checks that the remote ones exist.
+    pub fn dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
+    /// Fails if `size` bytes of `source` are more than its limit, that of the longest URL prefix
+    /// with one or `max_size`.
+    fn check_size(&self, source: &Source, size: u64) -> Result<()> {
+        let limit = self
+            .size_limits
+            .iter()
+            .filter(|(url, _)| source.url.starts_with(url.as_str()))
+            .max_by_key(|(url, _)| url.len())
+            .map(|(_, limit)| *limit)
+            .or(self.max_size);
+        match limit {
+            Some(limit) if size > limit => Err(Error::new(
+                ErrorKind::FileTooLarge,
+                format!(
+                    "{}: {} is more than the limit of {}",
+                    source,
+                    HumanBytes(size),
+                    HumanBytes(limit)
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Where the file of `source` is once downloaded, if it is kept.
+    pub fn path(&self, source: &Source) -> Option<PathBuf> {
+        if source.is_local() {
+            Some(PathBuf::from(&source.filename))
+        } else if self.keep {
+            Some(self.destination(&source.filename))
+        } else {
+            None
+        }
+    }
+
+    async fn hash_file(&self, path: &Path, digests: &mut Digests) -> Result<()> {
+        let context = |e: Error| Error::new(e.kind(), format!("{}: {}", path.display(), e));
+        let mut file = tokio::fs::File::open(path).await.map_err(context)?;
+        let hashing = Hashing::start(std::mem::take(digests), self.hash_threads);
+        let mut buf = vec![0; self.buffer_size];
+        loop {
+            let len = match file.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(context(e)),
+            };
+            hashing
+                .input(bytes::Bytes::copy_from_slice(&buf[..len]))
+                .await;
+        }
+        *digests = hashing.finish().await?;
+        Ok(())
+    }
+
+    /// Where a remote source named `filename` is downloaded to.
+    fn destination(&self, filename: &str) -> PathBuf {
+        match &self.srcdest {
+            Some(srcdest) => srcdest.join(filename),
+            None => PathBuf::from(filename),
+        }
+    }
+
+    /// The download agent for `source`, if it is not downloaded natively.
+    fn agent(&self, source: &Source) -> Option<&str> {
+        if let Some((_, command)) = self
+            .overrides
+            .iter()
+            .find(|(regex, _)| regex.is_match(&source.url))
+        {
+            return command.as_deref();
+        }
+        let protocol = crate::protocol(&source.url);
+        if let Some(command) = self
+            .external
+            .filter(|_| protocol == "http" || protocol == "https")
+        {
+            return Some(command);
+        }
+        if !self.all_agents && NATIVE_PROTOCOLS.contains(&protocol) {
+            return None;
+        }
+        self.agents
+            .iter()
+            .find(|(agent_protocol, _)| agent_protocol == protocol)
+            .map(|(_, command)| command.as_str())
+            .or_else(|| {
+                DEFAULT_AGENTS
+                    .iter()
+                    .find(|(agent_protocol, _)| *agent_protocol == protocol)
+                    .map(|(_, command)| *command)
+            })
+    }
+
+    /// Downloads `source` with the agent's command line the way makepkg runs it: `%u` is the URL
+    /// and `%o` the output file, and without `%o` the agent writes to stdout.
+    async fn run_agent(&self, agent: &str, source: &Source, dest: &Path) -> Result<()> {
+        // Absolute, as aria2c puts the output under its own directory.
+        let part = std::path::absolute(part_file(dest))?;
+        let output = part.to_string_lossy();
+        let url = self.rewrite(&source.url);
+        if self.hosts.pinned(&url) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "refusing to download {} with an agent, which cannot check the pinned \
+                     certificates of its host",
+                    url
+                ),
+            ));
+        }
+        // Like makepkg, as scp does not understand URLs.
+        let url = url.strip_prefix("scp://").unwrap_or(&url);
+        let mut args = agent
+            .split_whitespace()
+            .map(|arg| arg.replace("%u", url).replace("%o", &output));
+        let program = args.next().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("empty download agent for {}", source),
+            )
+        })?;
+        let mut command = tokio::process::Command::new(&program);
+        command.args(args).kill_on_drop(true);
+        debug!("running {:?}", command.as_std());
+        if agent.contains("%o") {
+            // Keep the agent's output out of the PKGBUILD printed on stdout.
+            command.stdout(std::io::stderr());
+        } else {
+            command.stdout(std::fs::File::create(&part)?);
+        }
+        let status = command
+            .status()
+            .await
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", program, e)))?;
+        if !status.success() {
+            return Err(Error::other(format!(
+                "{}: {} failed with {}",
+                source, program, status
+            )));
+        }
+        tokio::fs::rename(&part, dest).await
+    }
+
+    /// Starts a request, authenticated with the credentials configured for its host or else
+    /// the netrc login for it, and with the cookies for its URL.
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let url = self.rewrite(url);
+        debug!("{} {}", method, url);
+        let mut request = self.client.request(method, &url);
+        let url = match reqwest::Url::parse(&url) {
+            Ok(url) => url,
+            Err(_) => return request,
+        };
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let credential = self
+            .credentials
+            .iter()
+            .find(|credential| host_matches(&host, &credential.host.to_ascii_lowercase()));
+        let mut cookies: Vec<_> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.matches(&url))
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        if let Some(credential) = credential {
+            cookies.extend(
+                credential
+                    .cookies
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value)),
+            );
+        }
+        if !cookies.is_empty() {
+            request = request.header(reqwest::header::COOKIE, cookies.join("; "));
+        }
+        if let Some(credential) = credential {
+            if let Some(token) = &credential.token {
+                request = request.bearer_auth(token);
+            }
+            if let Some(username) = &credential.username {
+                request = request.basic_auth(username, credential.password.as_ref());
+            }
+            for (name, value) in &credential.headers {
+                request = request.header(name.as_str(), value.as_str());
+            }
+        } else if let Some(login) = self
+            .logins
+            .iter()
+            .find(|login| login.machine.as_deref() == Some(host.as_str()))
+            .or_else(|| self.logins.iter().find(|login| login.machine.is_none()))
+        {
+            request = request.basic_auth(&login.login, Some(&login.password));
+        }
+        request
+    }
+
+    /// Whether a HEAD request for `url` succeeds.
+    pub fn exists(&self, url: &str) -> bool {
+        if self.offline {
+            return false;
+        }
+        if let Err(e) = self.check_host(url) {
+            debug!("{}", e);
+            return false;
+        }
+        self.runtime
+            .block_on(self.retrying(|| async {
+                match self.request(reqwest::Method::HEAD, url).send().await {
+                    Ok(response) => match response.error_for_status() {
+                        Ok(_) => Ok(true),
+                        Err(e) if is_transient(&e) => Err(network_error(url, e)),
+                        Err(_) => Ok(false),
+                    },
+                    Err(e) => Err(network_error(url, e)),
+                }
+            }))
+            .unwrap_or(false)
+    }
+
+    /// The text at `url`, or `None` if it is not found.
+    pub fn fetch_text(&self, url: &str) -> Result<Option<String>> {
+        if self.offline {
+            return Err(not_offline(url));
+        }
+        self.check_host(url)?;
+        self.runtime.block_on(self.retrying(|| async {
+            let response = self
+                .request(reqwest::Method::GET, url)
+                .send()
+                .await
+                .map_err(|e| network_error(url, e))?;
+            if [reqwest::StatusCode::NOT_FOUND, reqwest::StatusCode::GONE]
+                .contains(&response.status())
+            {
+                return Ok(None);
+            }
+            let response = response
+                .error_for_status()
+                .map_err(|e| network_error(url, e))?;
+            let text = response.text().await.map_err(|e| network_error(url, e))?;
+            Ok(Some(text))
+        }))
+    }
+
+    /// Runs `attempt` until it succeeds, fails for good or runs out of retries.
+    async fn retrying<T, F: std::future::Future<Output = Result<T>>>(
+        &self,
+        mut attempt: impl FnMut() -> F,
+    ) -> Result<T> {
+        let mut retries = 0;
+        loop {
+            match attempt().await {
+                Err(ref e) if retries < self.retry.retries && is_transient_error(e) => {
+                    retries += 1;
+                    let delay = self.retry.delay(retries);
+                    warn!(
+                        "{}, retrying in {:.1}s ({}/{})",
+                        e,
+                        delay.as_secs_f64(),
+                        retries,
+                        self.retry.retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Downloads and hashes `sources` with up to `jobs` at a time, leaving out VCS sources and
+    /// those whose current sums are all `SKIP`. Files already on disk are used if they still
+    /// have the current sums of `unchanged` sources, or the size the server reports for the
+    /// others. Returns the sums, the sources that were found by one of `rewrites` with the
+    /// rewrite that found them, and those that failed. The first failure cancels the downloads
+    /// still running, unless keeping going, when failed sources keep their old sums if they did
+    /// not change and get placeholders if they did.
+    #[allow(clippy::too_many_arguments)]
+    pub fn download<'a>(
+        &self,
+        sources: &[Source],
+        unchanged: &[bool],
+        hash_names: &[String],
+        old_sums: &[Vec<String>],
+        rewrites: &'a [UrlRewrite],
+        remote: bool,
+        jobs: usize,
+    ) -> Result<(Vec<Vec<String>>, Moved<'a>, Failed)> {
+        let skipped: Vec<bool> = (0..sources.len())
+            .map(|i| {
+                !sources[i].is_signature()
+                    && !old_sums.is_empty()
+                    && old_sums
+                        .iter()
+                        .all(|sums| sums.get(i).map(String::as_str) == Some("SKIP"))
+            })
+            .collect();
+        if remote {
+            let refused: Vec<String> = sources
+                .iter()
+                .zip(&skipped)
+                .filter(|(source, skipped)| !**skipped && !source.is_vcs() && !source.is_local())
+                .filter_map(|(source, _)| {
+                    let url = self.rewrite(&source.url);
+                    let reason = self.hosts.refusal(&url)?;
+                    Some(format!("{} ({})", url, reason))
+                })
+                .collect();
+            if !refused.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!("refusing to download {}", refused.join(", ")),
+                ));
+            }
+        }
+        if remote && self.preflight && !self.offline {
+            let fetched: Vec<&Source> = sources
+                .iter()
+                .zip(&skipped)
+                .filter(|(source, skipped)| {
+                    !**skipped
+                        && self.fetched_natively(source)
+                        && !(self.reuse && self.destination(&source.filename).exists())
+                })
+                .map(|(source, _)| source)
+                .collect();
+            let sizes = match self
+                .runtime
+                .block_on(self.preflight(&fetched, rewrites, jobs))
+            {
+                Ok(sizes) => sizes,
+                Err(e) if self.keep_going => {
+                    warn!("{}", e);
+                    Vec::new()
+                }
+                Err(e) => return Err(e),
+            };
+            if !self.keep_going {
+                for (source, size) in fetched.iter().zip(&sizes) {
+                    if let Some(size) = size {
+                        self.check_size(source, *size)?;
+                    }
+                }
+            }
+            let total: u64 = sizes.iter().flatten().sum();
+            if total > 0 && self.keep && !self.dry_run {
+                let dir = self.srcdest.clone().unwrap_or_else(|| PathBuf::from("."));
+                let headroom = (total / 10).max(MIN_HEADROOM);
+                let available = available_space(&dir)?;
+                if available < total + headroom {
+                    return Err(Error::new(
+                        ErrorKind::StorageFull,
+                        format!(
+                            "{} has {} free, but the sources need {} and {} to spare",
+                            dir.display(),
+                            HumanBytes(available),
+                            HumanBytes(total),
+                            HumanBytes(headroom)
+                        ),
+                    ));
+                }
+            }
+        }
+        // Sources listed more than once, here or in an array downloaded before, are fetched once.
+        let first: Vec<usize> = (0..sources.len())
+            .map(|i| {
+                (0..i)
+                    .find(|&j| {
+                        skipped[j] == skipped[i] && sources[j].to_string() == sources[i].to_string()
+                    })
+                    .unwrap_or(i)
+            })
+            .collect();
+        let unique = (0..sources.len()).filter(|&i| first[i] == i);
+        let downloads = stream::iter(unique.map(|i| (i, &sources[i], skipped[i])))
+            .map(|(i, source, skipped)| {
+                let known: Option<Vec<String>> =
+                    if unchanged.get(i) == Some(&true) && old_sums.len() == hash_names.len() {
+                        old_sums
+                            .iter()
+                            .map(|sums| sums.get(i).filter(|sum| is_sum(sum)).cloned())
+                            .collect()
+                    } else {
+                        None
+                    };
+                // A retry resumes from what the failed attempt left in the `.part` file.
+                let download = self.retrying(move || {
+                    self.download_source(
+                        source,
+                        skipped,
+                        known.clone(),
+                        hash_names,
+                        rewrites,
+                        remote,
+                    )
+                });
+                let key = hashed_key(source, hash_names);
+                async move {
+                    let hashed = self.hashed.lock().unwrap().get(&key).cloned();
+                    if let Some(sums) = hashed.filter(|_| remote && !skipped) {
+                        info!("{} (already downloaded)", source.url);
+                        return Ok(Ok((sums, None)));
+                    }
+                    match download.await {
+                        Ok((sums, None))
+                            if remote && !skipped && !source.is_vcs() && !self.dry_run =>
+                        {
+                            self.hashed.lock().unwrap().insert(key, sums.clone());
+                            Ok(Ok((sums, None)))
+                        }
+                        Err(e) if self.keep_going => Ok(Err(e)),
+                        result => result.map(Ok),
+                    }
+                }
+            })
+            .buffered(jobs.max(1))
+            .try_collect();
+        let results: Vec<_> = self.runtime.block_on(async {
+            // Stopping the downloads on Ctrl-C, rather than being killed, cleans up after them.
+            match future::select(downloads, Box::pin(tokio::signal::ctrl_c())).await {
+                future::Either::Left((results, _)) => results,
+                future::Either::Right(_) => Err(Error::new(ErrorKind::Interrupted, "interrupted")),
+            }
+        })?;
+
+        let mut results = results.into_iter();
+        let mut downloaded: Vec<Option<(Vec<String>, Option<&UrlRewrite>)>> =
+            Vec::with_capacity(sources.len());
+        let mut digest_hashes: Vec<Vec<String>> = vec![Vec::new(); hash_names.len()];
+        let mut moved = Vec::new();
+        let mut failed = Vec::new();
+        for i in 0..sources.len() {
+            // A duplicate shares the outcome of the first listing, without failing a second time.
+            let result = if first[i] == i {
+                results.next().unwrap().map_err(Some)
+            } else {
+                info!("{} (listed again)", sources[i].url);
+                downloaded[first[i]].clone().ok_or(None)
+            };
+            downloaded.push(result.as_ref().ok().cloned());
+            let (sums, rewrite) = match result {
+                Ok(downloaded) => downloaded,
+                Err(e) => {
+                    let keep =
+                        unchanged.get(i) == Some(&true) && old_sums.len() == hash_names.len();
+                    let sums = (0..hash_names.len())
+                        .map(|h| {
+                            old_sums
+                                .get(h)
+                                .and_then(|sums| sums.get(i))
+                                .filter(|_| keep)
+                                .cloned()
+                                .unwrap_or_else(|| PLACEHOLDER_SUM.to_string())
+                        })
+                        .collect();
+                    failed.extend(e.map(|e| (i, e)));
+                    (sums, None)
+                }
+            };
+            for (hashes, sum) in digest_hashes.iter_mut().zip(sums) {
+                hashes.push(sum);
+            }
+            if let Some(rewrite) = rewrite {
+                moved.push((i, rewrite));
+            }
+        }
+        Ok((digest_hashes, moved, failed))
+    }
+
+    /// Whether `source` is downloaded over HTTP(S) by pkgbump itself.
+    fn fetched_natively(&self, source: &Source) -> bool {
+        !source.is_vcs()
+            && ["http", "https"].contains(&crate::protocol(&source.url))
+            && self.agent(source).is_none()
+    }
+
+    /// Checks with HEAD requests that all `sources`, or one of their mirrors or rewrites,
+    /// exist before downloading any, and returns their sizes where the servers report them.
+    /// Only missing sources are errors, others are left to the download to report.
+    async fn preflight(
+        &self,
+        sources: &[&Source],
+        rewrites: &[UrlRewrite],
+        jobs: usize,
+    ) -> Result<Vec<Option<u64>>> {
+        let results: Vec<_> = stream::iter(sources)
+            .map(|source| async move {
+                let mut urls = vec![source.url.clone()];
+                urls.extend(self.mirror_urls(&source.url));
+                urls.extend(
+                    rewrites
+                        .iter()
+                        .filter_map(|rewrite| rewrite.apply(&source.url))
+                        .filter(|url| !self.refuses(url)),
+                );
+                let mut missing = None;
+                for url in urls {
+                    match self.request(reqwest::Method::HEAD, &url).send().await {
+                        Ok(response)
+                            if matches!(
+                                response.status(),
+                                reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE
+                            ) =>
+                        {
+                            missing.get_or_insert(response.status());
+                        }
+                        Ok(response) if response.status().is_success() => {
+                            return Ok(content_length(&response));
+                        }
+                        _ => return Ok(None),
+                    }
+                }
+                Err(format!("{}: {}", source, missing.unwrap()))
+            })
+            .buffered(jobs.max(1))
+            .collect()
+            .await;
+        let missing: Vec<_> = results
+            .iter()
+            .filter_map(|result| result.as_ref().err())
+            .collect();
+        if !missing.is_empty() {
+            let list: Vec<String> = missing
+                .iter()
+                .map(|error| format!("\n  {}", error))
+                .collect();
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{} missing:{}", count_sources(missing.len()), list.concat()),
+            ));
+        }
+        let sizes: Vec<Option<u64>> = results.into_iter().map(|result| result.unwrap()).collect();
+        if !sizes.is_empty() {
+            let total = HumanBytes(sizes.iter().flatten().sum());
+            let verb = if self.dry_run {
+                "Would download"
+            } else {
+                "Downloading"
+            };
+            if sizes.iter().all(Option::is_some) {
+                info!(
+                    "{} {}, {} in total",
+                    verb,
+                    count_sources(sizes.len()),
+                    total
+                );
+            } else {
+                info!(
+                    "{} {}, at least {}",
+                    verb,
+                    count_sources(sizes.len()),
+                    total
+                );
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// Downloads and hashes one source, or only reports it if it is `skipped`, a VCS source or
+    /// a remote one without `remote`. Returns its sums in the order of `hash_names`, and the
+    /// rewrite that found it if it moved. A source with `known` sums is not downloaded again if
+    /// the file on disk still has them.
+    async fn download_source<'a>(
+        &self,
+        source: &Source,
+        skipped: bool,
+        known: Option<Vec<String>>,
+        hash_names: &[String],
+        rewrites: &'a [UrlRewrite],
+        remote: bool,
+    ) -> Result<(Vec<String>, Option<&'a UrlRewrite>)> {
+        let skip = if source.is_vcs() {
+            Some("VCS")
+        } else if skipped {
+            Some("SKIP")
+        } else if self.dry_run {
+            Some("dry run")
+        } else if !remote && !source.is_local() {
+            Some("not downloaded")
+        } else {
+            None
+        };
+        if let Some(reason) = skip {
+            info!(
+                "{} -> {} ({}, skipped)",
+                source.url, source.filename, reason
+            );
+            let sums = match (reason, known) {
+                ("dry run", Some(known)) => known,
+                ("not downloaded" | "dry run", _) if !source.is_signature() => {
+                    vec![PLACEHOLDER_SUM.to_string(); hash_names.len()]
+                }
+                _ => vec!["SKIP".to_string(); hash_names.len()],
+            };
+            return Ok((sums, None));
+        }
+
+        let mut digests = digests(hash_names)?;
+        let mut moved = None;
+        let dest = self.destination(&source.filename);
+        let cached = match &self.cache {
+            Some(cache) if self.reuse && !source.is_local() => {
+                cache.lookup(&source.url).await.unwrap_or_else(|e| {
+                    warn!("cannot read the download cache: {}", e);
+                    None
+                })
+            }
+            _ => None,
+        };
+        let mut downloaded = None;
+        if self.reuse && self.srcdest.is_none() && !source.is_local() {
+            if let Some(sums) = self
+                .verify_existing(source, &dest, known.as_deref(), hash_names)
+                .await
+            {
+                info!("{} (exists, verified)", dest.display());
+                return Ok((sums, None));
+            }
+        }
+        if source.is_local() {
+            info!("{} (local)", source.filename);
+            self.hash_file(Path::new(&source.filename), &mut digests)
+                .await?;
+        } else if self.reuse && self.srcdest.is_some() && tokio::fs::metadata(&dest).await.is_ok() {
+            // makepkg builds with the file already in SRCDEST, so its sums are the ones to use.
+            info!("{} (in SRCDEST)", dest.display());
+            self.hash_file(&dest, &mut digests).await?;
+        } else if let Some(cached) = cached.as_ref().filter(|cached| {
+            // Offline there is no asking the server whether the cached file is current.
+            self.offline || cached.validators.is_empty() || !self.fetched_natively(source)
+        }) {
+            if self.keep {
+                info!("{} -> {} (cached)", source.url, dest.display());
+                copy_atomically(&cached.path, &dest).await?;
+                set_modified(&dest, &cached.validators).await?;
+                self.hash_file(&dest, &mut digests).await?;
+            } else {
+                info!("{} (cached)", source.url);
+                self.hash_file(&cached.path, &mut digests).await?;
+            }
+        } else if self.offline && crate::protocol(&source.url) != "file" {
+            return Err(not_offline(&source.url));
+        } else if let Some(agent) = self.agent(source) {
+            let from_makepkg = self.agents.iter().any(|(_, command)| command == agent)
+                || DEFAULT_AGENTS.iter().any(|(_, command)| *command == agent);
+            let via = if from_makepkg {
+                "DLAGENTS"
+            } else {
+                agent.split(' ').next().unwrap_or(agent)
+            };
+            info!("{} -> {} ({})", source.url, dest.display(), via);
+            self.run_agent(agent, source, &dest).await?;
+            if let Err(e) = self.check_size(source, tokio::fs::metadata(&dest).await?.len()) {
+                tokio::fs::remove_file(&dest).await?;
+                return Err(e);
+            }
+            self.hash_file(&dest, &mut digests).await?;
+            if self.keep {
+                downloaded = Some((source.url.clone(), dest, Validators::default()));
+            } else {
+                // Agents can only write files, so this one goes once it is hashed.
+                tokio::fs::remove_file(&dest).await?;
+            }
+        } else if crate::protocol(&source.url) == "file" {
+            let path = reqwest::Url::parse(&source.url)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{}: not a local path", source),
+                    )
+                })?;
+            if self.keep {
+                info!("{} -> {} (copied)", source.url, dest.display());
+                copy_atomically(&path, &dest).await?;
+                self.hash_file(&dest, &mut digests).await?;
+            } else {
+                info!("{} (not kept)", source.url);
+                self.hash_file(&path, &mut digests).await?;
+            }
+        } else {
+            let protocol = crate::protocol(&source.url);
+            if !NATIVE_PROTOCOLS.contains(&protocol) {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("{}: no download agent for {} in DLAGENTS", source, protocol),
+                ));
+            }
+            if self.keep {
+                info!("{} -> {}", source.url, dest.display());
+                let (rewrite, fetched) = self
+                    .download_native(source, dest, rewrites, cached.as_ref(), &mut digests)
+                    .await?;
+                moved = rewrite;
+                downloaded = fetched;
+            } else {
+                info!("{} (not kept)", source.url);
+                moved = self
+                    .hash_native(source, rewrites, cached.as_ref(), &mut digests)
+                    .await?;
+            }
+        }
+        if let (Some(cache), Some((url, dest, validators))) = (&self.cache, downloaded) {
+            if let Err(e) = cache.store(&url, &dest, validators).await {
+                warn!("cannot add {} to the download cache: {}", dest.display(), e);
+            }
+        }
+
+        Ok((sums(source, hash_names, digests), moved))
+    }
+
+    /// The sums of the file already at `dest` if it is the one `source` would download: it
+    /// still has the `known` sums, or without any the size the server reports.
+    async fn verify_existing(
+        &self,
+        source: &Source,
+        dest: &Path,
+        known: Option<&[String]>,
+        hash_names: &[String],
+    ) -> Option<Vec<String>> {
+        let len = tokio::fs::metadata(dest).await.ok()?.len();
+        // Offline the file on disk is all there is, like one in SRCDEST.
+        if known.is_none() && !self.offline {
+            if self.agent(source).is_some() {
+                return None;
+            }
+            let response = self
+                .request(reqwest::Method::HEAD, &source.url)
+                .send()
+                .await
+                .ok()?
+                .error_for_status()
+                .ok()?;
+            if content_length(&response)? != len {
+                return None;
+            }
+        }
+        let mut digests = digests(hash_names).ok()?;
+        self.hash_file(dest, &mut digests).await.ok()?;
+        let sums = sums(source, hash_names, digests);
+        match known {
+            Some(known) if known != sums.as_slice() => {
+                info!("{} changed, downloading it again", dest.display());
+                None
+            }
+            _ => Some(sums),
+        }
+    }
+
+    /// Downloads a remote source to `dest` over HTTP(S), feeding it to `digests`. A `cached`
+    /// copy with validators is used if the server says it did not change. Returns the rewrite
+    /// that found the source if it moved, and the URL, file and validators of what was
+    /// downloaded, if anything.
+    async fn download_native<'a>(
+        &self,
+        source: &Source,
+        dest: PathBuf,
+        rewrites: &'a [UrlRewrite],
+        cached: Option<&Cached>,
+        digests: &mut Digests,
+    ) -> Result<(
+        Option<&'a UrlRewrite>,
+        Option<(String, PathBuf, Validators)>,
+    )> {
+        // Downloads go to a `.part` file first, which the next run resumes if this one fails.
+        let part = part_file(&dest);
+        let offset = match cached {
+            Some(_) => 0,
+            None => tokio::fs::metadata(&part)
+                .await
+                .map_or(0, |part| part.len()),
+        };
+        // Without `If-Range`, a server whose file changed would send the rest of the new one.
+        let resume = match offset {
+            0 => None,
+            _ => read_part_validators(&part).await,
+        };
+        let validators = cached.map(|cached| &cached.validators);
+        let (mut response, found) = self
+            .fetch(source, rewrites, offset, resume.as_ref(), validators)
+            .await?;
+        if let (reqwest::StatusCode::NOT_MODIFIED, Some(cached)) = (response.status(), cached) {
+            info!("{}: not modified, using the cached copy", dest.display());
+            copy_atomically(&cached.path, &dest).await?;
+            set_modified(&dest, &cached.validators).await?;
+            self.hash_file(&dest, digests).await?;
+            return Ok((None, None));
+        }
+        let mut moved = None;
+        let (filename, dest, url) = match found {
+            Some((rewrite, found)) => {
+                let dest = self.destination(&found.filename);
+                info!("{} -> {} (moved)", found.url, dest.display());
+                moved = Some(rewrite);
+                (found.filename, dest, found.url)
+            }
+            None => (source.filename.clone(), dest, source.url.clone()),
+        };
+        let validators = Validators {
+            etag: header(&response, reqwest::header::ETAG),
+            last_modified: header(&response, reqwest::header::LAST_MODIFIED),
+        };
+        let part = part_file(&dest);
+        let resumed = is_resumed(&response, offset);
+        let done = if resumed { offset } else { 0 };
+        let total = response.content_length().map(|len| len + done);
+        if let Some(total) = total {
+            self.check_size(source, total)?;
+        }
+        let file = if resumed {
+            info!("{}: resuming after {} bytes", dest.display(), offset);
+            self.hash_file(&part, digests).await?;
+            tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(&part)
+                .await?
+        } else {
+            let file = tokio::fs::File::create(&part).await?;
+            write_part_validators(&part, &validators).await?;
+            file
+        };
+        let mut file = tokio::io::BufWriter::with_capacity(self.buffer_size, file);
+        let mut progress = self.progress.start(&filename, total);
+        progress.advance(done as usize);
+        let segments = match total {
+            Some(total) if !resumed && accepts_ranges(&response) => {
+                self.segments.min(total / MIN_SEGMENT_SIZE)
+            }
+            _ => 1,
+        };
+        if segments > 1 {
+            drop(file);
+            let total = total.unwrap();
+            self.download_segments(source, response, &part, total, segments, &mut progress)
+                .await?;
+            self.hash_file(&part, digests).await?;
+        } else {
+            let mut limit = self.limit_rate_per_source.map(RateLimit::new);
+            let hashing = Hashing::start(std::mem::take(digests), self.hash_threads);
+            let mut received = done;
+            while let Some(chunk) = response
+                .chunk()
+                .await
+                .map_err(|e| network_error(source, e))?
+            {
+                file.write_all(&chunk).await?;
+                progress.advance(chunk.len());
+                let len = chunk.len();
+                received += len as u64;
+                // Servers that do not announce the length are only stopped once past the limit.
+                if let Err(e) = self.check_size(source, received) {
+                    drop(file);
+                    remove_part(&part).await?;
+                    return Err(e);
+                }
+                hashing.input(chunk).await;
+                pause(self.throttle(&mut limit, len)).await;
+            }
+            file.flush().await?;
+            if let Err(e) = check_length(source, received, total) {
+                if !is_transient_error(&e) {
+                    // Resuming from a file with too much in it would keep the extra bytes.
+                    remove_part(&part).await?;
+                }
+                return Err(e);
+            }
+            *digests = hashing.finish().await?;
+        }
+        progress.finish();
+        tokio::fs::rename(&part, &dest).await?;
+        let _ = tokio::fs::remove_file(part_validators_file(&part)).await;
+        set_modified(&dest, &validators).await?;
+        Ok((moved, Some((url, dest, validators))))
+    }
+
+    /// Feeds a remote source to `digests` as it downloads over HTTP(S), without writing it
+    /// anywhere. Returns the rewrite that found the source if it moved.
+    async fn hash_native<'a>(
+        &self,
+        source: &Source,
+        rewrites: &'a [UrlRewrite],
+        cached: Option<&Cached>,
+        digests: &mut Digests,
+    ) -> Result<Option<&'a UrlRewrite>> {
+        let validators = cached.map(|cached| &cached.validators);
+        let (mut response, found) = self.fetch(source, rewrites, 0, None, validators).await?;
+        if let (reqwest::StatusCode::NOT_MODIFIED, Some(cached)) = (response.status(), cached) {
+            info!("{}: not modified, using the cached copy", source.filename);
+            self.hash_file(&cached.path, digests).await?;
+            return Ok(None);
+        }
+        let (filename, moved) = match found {
+            Some((rewrite, found)) => {
+                info!("{} (moved)", found.url);
+                (found.filename, Some(rewrite))
+            }
+            None => (source.filename.clone(), None),
+        };
+        let total = response.content_length();
+        if let Some(total) = total {
+            self.check_size(source, total)?;
+        }
+        let mut progress = self.progress.start(&filename, total);
+        let mut limit = self.limit_rate_per_source.map(RateLimit::new);
+        let hashing = Hashing::start(std::mem::take(digests), self.hash_threads);
+        let mut received = 0;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| network_error(source, e))?
+        {
+            progress.advance(chunk.len());
+            let len = chunk.len();
+            received += len as u64;
+            self.check_size(source, received)?;
+            hashing.input(chunk).await;
+            pause(self.throttle(&mut limit, len)).await;
+        }
+        check_length(source, received, total)?;
+        *digests = hashing.finish().await?;
+        progress.finish();
+        Ok(moved)
+    }
+
+    /// Downloads the `len` bytes of a source to `part` in `segments` ranges at once, the first of
+    /// them from `response` of the whole source.
+    async fn download_segments(
+        &self,
+        source: &Source,
+        response: reqwest::Response,
+        part: &Path,
+        len: u64,
+        segments: u64,
+        progress: &mut progress::Download,
+    ) -> Result<()> {
+        let partial = Partial::new(part);
+        tokio::fs::File::create(part).await?.set_len(len).await?;
+        let download = Segments {
+            source,
+            url: response.url().to_string(),
+            part,
+            progress: Mutex::new(progress),
+            limit: Mutex::new(self.limit_rate_per_source.map(RateLimit::new)),
+        };
+        let size = len.div_ceil(segments);
+        let mut response = Some(response);
+        futures::future::try_join_all((0..segments).map(|i| {
+            let start = i * size;
+            self.download_segment(&download, response.take(), start, (start + size).min(len))
+        }))
+        .await?;
+        partial.finish();
+        Ok(())
+    }
+
+    /// Downloads the bytes from `start` up to `end` of a segmented download, from `response`
+    /// if it already starts there.
+    async fn download_segment(
+        &self,
+        download: &Segments<'_>,
+        response: Option<reqwest::Response>,
+        start: u64,
+        end: u64,
+    ) -> Result<()> {
+        let mut response = match response {
+            Some(response) => response,
+            None => {
+                let response = self
+                    .request(reqwest::Method::GET, &download.url)
+                    .header(
+                        reqwest::header::RANGE,
+                        format!("bytes={}-{}", start, end - 1),
+                    )
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| network_error(download.source, e))?;
+                if !is_resumed(&response, start) {
+                    return Err(Error::other(format!(
+                        "{}: the server ignored the range of a segment",
+                        download.source
+                    )));
+                }
+                response
+            }
+        };
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(download.part)
+            .await?;
+        file.seek(std::io::SeekFrom::Start(start)).await?;
+        let mut file = tokio::io::BufWriter::with_capacity(self.buffer_size, file);
+        let mut remaining = end - start;
+        while remaining > 0 {
+            let chunk = match response
+                .chunk()
+                .await
+                .map_err(|e| network_error(download.source, e))?
+            {
+                Some(chunk) => chunk,
+                None => break,
+            };
+            // The first segment comes from a response of the whole source.
+            let chunk = &chunk[..chunk.len().min(remaining as usize)];
+            file.write_all(chunk).await?;
+            remaining -= chunk.len() as u64;
+            download.progress.lock().unwrap().advance(chunk.len());
+            let delay = self.throttle(&mut download.limit.lock().unwrap(), chunk.len());
+            pause(delay).await;
+        }
+        if remaining > 0 {
+            return Err(Error::other(Transient(format!(
+                "{}: connection closed {} bytes before the end of a segment",
+                download.source, remaining
+            ))));
+        }
+        file.flush().await
+    }
+
+    /// Requests a remote source from `offset` on, trying its mirrors in order if that fails, and
+    /// then the `rewrites` of its URL if it is not found. The response may be the whole source,
+    /// see `is_resumed`, which it is if it changed since `resume`, what the part downloaded so
+    /// far was.
+    async fn fetch<'a>(
+        &self,
+        source: &Source,
+        rewrites: &'a [UrlRewrite],
+        offset: u64,
+        resume: Option<&Validators>,
+        validators: Option<&Validators>,
+    ) -> Result<(reqwest::Response, Option<(&'a UrlRewrite, Source)>)> {
+        let get = |url: &str, offset: u64| {
+            let mut request = self.request(reqwest::Method::GET, url);
+            if offset > 0 {
+                request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+                if let Some(if_range) = resume.and_then(Validators::if_range) {
+                    request = request.header(reqwest::header::IF_RANGE, if_range);
+                }
+            }
+            if let Some(validators) = validators.filter(|_| url == source.url) {
+                if let Some(etag) = &validators.etag {
+                    request = request.header(reqwest::header::IF_NONE_MATCH, etag.as_str());
+                }
+                if let Some(last_modified) = &validators.last_modified {
+                    request =
+                        request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified.as_str());
+                }
+            }
+            let requested = self.rewrite(url);
+            async move {
+                let response = request.send().await?;
+                debug!("{} {}", response.status(), response.url());
+                report_redirect(source, &requested, &response);
+                response.error_for_status()
+            }
+        };
+        let url = self.rewrite(&source.url);
+        if url != source.url {
+            info!("{}: downloading from {}", source.filename, url);
+        }
+        let mut result = get(&source.url, offset).await;
+        let resumable = match &result {
+            Ok(response) => {
+                response.status() != reqwest::StatusCode::PARTIAL_CONTENT
+                    || is_resumed(response, offset)
+            }
+            Err(e) => e.status() != Some(reqwest::StatusCode::RANGE_NOT_SATISFIABLE),
+        };
+        if !resumable {
+            // Start over rather than trust a partial file the server does not agree with.
+            result = get(&source.url, 0).await;
+        }
+        let error = match result {
+            Ok(response) => return Ok((response, None)),
+            Err(e) => e,
+        };
+        for url in self.mirror_urls(&source.url) {
+            match get(&url, 0).await {
+                Ok(response) => {
+                    info!("{} -> {} (mirror)", url, source.filename);
+                    return Ok((response, None));
+                }
+                Err(e) => warn!("{}", network_error(&url, e)),
+            }
+        }
+        if error.status() == Some(reqwest::StatusCode::NOT_FOUND) {
+            for rewrite in rewrites {
+                let url = match rewrite.apply(&source.url) {
+                    Some(url) if !self.refuses(&url) => url,
+                    _ => continue,
+                };
+                if let Ok(response) = get(&url, 0).await {
+                    let entry = match &source.rename {
+                        Some(rename) => format!("{}::{}", rename, url),
+                        None => url,
+                    };
+                    return Ok((response, Some((rewrite, Source::new(&entry)))));
+                }
+            }
+        }
+        Err(network_error(source, error))
+    }
+}
+
+fn seconds(seconds: f64, name: &str) -> Result<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid {} `{}`", name, seconds),
+        )
+    })
+}
+
+/// The space available to unprivileged users on the filesystem of `dir`.
+fn available_space(dir: &Path) -> Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes())
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stat` is only read after statvfs filled it in.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Parses a number of bytes like curl's `--limit-rate`, e.g. `500K` or `2M`.
+fn parse_size(rate: &str, name: &str) -> Result<u64> {
+    let (number, unit) = match rate.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(i) => rate.split_at(i),
+        None => (rate, ""),
+    };
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => 0,
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number * multiplier as f64 >= 1.0 => Ok((number * multiplier as f64) as u64),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid {} `{}`", name, rate),
+        )),
+    }
+}
+
+/// The proxies to use instead of the ones reqwest picks up from the environment by itself:
+/// `proxy` for everything, or the per-host rules followed by the environment's.
+fn proxies(config: &DownloadConfig) -> Result<Vec<reqwest::Proxy>> {
+    let invalid = |proxy: &str, e: reqwest::Error| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid proxy `{}`: {}", proxy, e),
+        )
+    };
+    if let Some(proxy) = &config.proxy {
+        return Ok(vec![
+            reqwest::Proxy::all(proxy.as_str()).map_err(|e| invalid(proxy, e))?
+        ]);
+    }
+    if config.proxies.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut rules = Vec::new();
+    for rule in &config.proxies {
+        let url = reqwest::Url::parse(&rule.proxy).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid proxy `{}`: {}", rule.proxy, e),
+            )
+        })?;
+        rules.push((rule.host.to_ascii_lowercase(), url));
+    }
+    let mut proxies = vec![reqwest::Proxy::custom(move |url| {
+        let host = url.host_str()?;
+        rules
+            .iter()
+            .find(|(domain, _)| host_matches(host, domain))
+            .map(|(_, proxy)| proxy.clone())
+    })];
+    let no_proxy = reqwest::NoProxy::from_env();
+    for (var, scheme) in [
+        ("https_proxy", "https"),
+        ("http_proxy", "http"),
+        ("all_proxy", ""),
+    ] {
+        let proxy = match std::env::var(var).or_else(|_| std::env::var(var.to_uppercase())) {
+            Ok(proxy) if !proxy.is_empty() => proxy,
+            _ => continue,
+        };
+        let proxy = match scheme {
+            "https" => reqwest::Proxy::https(proxy.as_str()),
+            "http" => reqwest::Proxy::http(proxy.as_str()),
+            _ => reqwest::Proxy::all(proxy.as_str()),
+        }
+        .map_err(|e| invalid(&proxy, e))?;
+        proxies.push(proxy.no_proxy(no_proxy.clone()));
+    }
+    Ok(proxies)
+}
+
+/// Follows up to `max_redirects`, and only to URLs the host policy allows.
+fn redirect_policy(config: &DownloadConfig) -> reqwest::redirect::Policy {
+    let max_redirects = config.max_redirects;
+    let hosts = HostPolicy::new(config);
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() > max_redirects {
+            attempt.error("too many redirects")
+        } else if let Some(reason) = hosts.refusal(attempt.url().as_str()) {
+            let error = format!("refusing a redirect to {} ({})", attempt.url(), reason);
+            attempt.error(error)
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// Resolves host names to the addresses of one IP version only, for networks where the other
+/// is broken.
+struct IpVersion(u8);
+
+impl reqwest::dns::Resolve for IpVersion {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let version = self.0;
+        Box::pin(async move {
+            let addrs: Vec<_> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| addr.is_ipv4() == (version == 4))
+                .collect();
+            if addrs.is_empty() {
+                let error = format!("{} has no IPv{} address", name.as_str(), version);
+                return Err(Error::new(ErrorKind::NotFound, error).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Whether `host` is `domain` or one of its subdomains.
+pub fn host_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.'))
+}
+
+/// Connection problems, timeouts, server errors and rate limiting.
+fn is_transient(e: &reqwest::Error) -> bool {
+    match e.status() {
+        Some(status) => {
+            status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        }
+        None => {
+            (e.is_timeout() || e.is_connect() || e.is_request() || e.is_body())
+                && !is_certificate_error(e)
+        }
+    }
+}
+
+/// Whether the server's certificate was rejected, which retrying does not change. Those that do
+/// not match their pins are general errors of rustls.
+fn is_certificate_error(e: &reqwest::Error) -> bool {
+    fn rejected(e: &(dyn std::error::Error + 'static)) -> bool {
+        match e.downcast_ref::<rustls::Error>() {
+            Some(e) => matches!(
+                e,
+                rustls::Error::InvalidCertificate(_) | rustls::Error::General(_)
+            ),
+            // An `io::Error` skips the error it wraps in its own sources.
+            None => e
+                .downcast_ref::<Error>()
+                .and_then(Error::get_ref)
+                .is_some_and(|inner| rejected(inner)),
+        }
+    }
+    let mut cause = std::error::Error::source(e);
+    while let Some(inner) = cause {
+        if rejected(inner) {
+            return true;
+        }
+        cause = inner.source();
+    }
+    false
+}
+
+fn is_transient_error(e: &Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<Transient>())
+}
+
+fn network_error(what: impl fmt::Display, e: reqwest::Error) -> Error {
+    let mut message = format!("{}: {}", what, e);
+    let mut cause = std::error::Error::source(&e);
+    while let Some(inner) = cause {
+        message.push_str(&format!(": {}", inner));
+        cause = inner.source();
+    }
+    if is_transient(&e) {
+        Error::other(Transient(message))
+    } else {
+        Error::other(message)
+    }
+}
+
+fn count_sources(count: usize) -> String {
+    match count {
+        1 => "1 source".to_string(),
+        _ => format!("{} sources", count),
+    }
+}
+
+/// The size of the file a HEAD `response` is about. Not `content_length()`, which is that of
+/// the empty body.
+fn content_length(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+fn header(response: &reqwest::Response, name: reqwest::header::HeaderName) -> Option<String> {
+    let value = response.headers().get(name)?;
+    value.to_str().ok().map(str::to_string)
+}
+
+/// Sets the modification time of the file at `path` to its `Last-Modified` time, like `curl -R`
+/// does, so that copies of the same upstream file look the same to tools like rsync.
+async fn set_modified(path: &Path, validators: &Validators) -> Result<()> {
+    let time = match validators.last_modified.as_deref().and_then(http_date) {
+        Some(time) => time,
+        None => return Ok(()),
+    };
+    let file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+    file.into_std().await.set_modified(time)
+}
+
+/// Parses a date in the format HTTP requires, like `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (_, date) = value.split_once(", ")?;
+    let fields: Vec<_> = date.split(' ').collect();
+    let (day, month, year, time) = match fields.as_slice() {
+        [day, month, year, time, "GMT"] => (day, month, year, time),
+        _ => return None,
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|name| name == month)? as u64 + 1;
+    let year: u64 = year.parse().ok()?;
+    let time: Vec<u64> = time
+        .split(':')
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    let (hours, minutes, seconds) = match time.as_slice() {
+        [hours, minutes, seconds] if *hours < 24 && *minutes < 60 && *seconds < 61 => {
+            (*hours, *minutes, *seconds)
+        }
+        _ => return None,
+    };
+    if year < 1970 || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days since the epoch of the civil date, counting years from March so leap days come last.
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+    let seconds = days * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Whether the server of `response` can send parts of the file.
+fn accepts_ranges(response: &reqwest::Response) -> bool {
+    response
+        .headers()
+        .get(reqwest::header::ACCEPT_RANGES)
+        .is_some_and(|ranges| ranges == "bytes")
+}
+
+async fn pause(delay: Duration) {
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Fails a download of `source` that got `received` bytes when the server said it would send
+/// `expected`. One cut short is retried, from where it stopped if it was kept.
+fn check_length(source: &Source, received: u64, expected: Option<u64>) -> Result<()> {
+    match expected {
+        Some(expected) if received < expected => Err(Error::other(Transient(format!(
+            "{}: connection closed {} bytes before the end",
+            source,
+            expected - received
+        )))),
+        Some(expected) if received > expected => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{}: received {} bytes, but the server announced {}",
+                source, received, expected
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Reports where a request for `url` of `source` ended up if it was redirected, with a warning
+/// if that is another host or a downgrade from HTTPS, as URLs tend to do once they rot.
+fn report_redirect(source: &Source, url: &str, response: &reqwest::Response) {
+    let (from, to) = match reqwest::Url::parse(url) {
+        Ok(from) if from != *response.url() => (from, response.url()),
+        _ => return,
+    };
+    info!("{}: redirected to {}", source.filename, to);
+    if from.scheme() == "https" && to.scheme() != "https" {
+        warn!(
+            "{} redirects from HTTPS to {}, consider updating the URL",
+            url, to
+        );
+    } else if from.host_str() != to.host_str() {
+        warn!(
+            "{} redirects to another host, consider updating the URL to {}",
+            url, to
+        );
+    }
+}
+
+/// Whether `response` continues a partial download of `offset` bytes.
+fn is_resumed(response: &reqwest::Response, offset: u64) -> bool {
+    offset > 0
+        && response.status() == reqwest::StatusCode::PARTIAL_CONTENT
+        && response
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|range| range.to_str().ok())
+            .is_some_and(|range| range.starts_with(&format!("bytes {}-", offset)))
+}
+
+/// Copies `from` to `dest` through a `.part` file, so that `dest` is never there only in part.
+async fn copy_atomically(from: &Path, dest: &Path) -> Result<()> {
+    let part = part_file(dest);
+    let partial = Partial::new(&part);
+    tokio::fs::copy(from, &part)
+        .await
+        .map_err(|e| Error::new(e.kind(), format!("{}: {}", from.display(), e)))?;
+    tokio::fs::rename(&part, dest).await?;
+    partial.finish();
+    Ok(())
+}
+
+fn not_offline(url: &str) -> Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!(
+            "{}: not in SRCDEST, the cache or the package directory",
+            url
+        ),
+    )
+}
+
+/// The agent of the external downloader `name`, or `None` for the builtin client.
+fn external_downloader(name: &str) -> Result<Option<&'static str>> {
+    if name == "builtin" {
+        return Ok(None);
+    }
+    EXTERNAL_DOWNLOADERS
+        .iter()
+        .find(|(downloader, _)| *downloader == name)
+        .map(|(_, command)| Some(*command))
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "unknown downloader `{}`, expected builtin, curl or aria2c",
+                    name
+                ),
+            )
+        })
+}
+
+/// What identifies a download of `source` with the algorithms of `hash_names`.
+fn hashed_key(source: &Source, hash_names: &[String]) -> String {
+    format!("{} {}", source, hash_names.join(","))
+}
+
+/// Where a download to `path` goes until it is complete.
+fn part_file(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Where the validators of the server that sent the `part` downloaded so far are kept.
+fn part_validators_file(part: &Path) -> PathBuf {
+    let mut validators = part.as_os_str().to_owned();
+    validators.push(".validators");
+    PathBuf::from(validators)
+}
+
+async fn read_part_validators(part: &Path) -> Option<Validators> {
+    let validators = tokio::fs::read(part_validators_file(part)).await.ok()?;
+    serde_json::from_slice(&validators).ok()
+}
+
+async fn write_part_validators(part: &Path, validators: &Validators) -> Result<()> {
+    let path = part_validators_file(part);
+    if validators.is_empty() {
+        return match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    tokio::fs::write(&path, serde_json::to_vec(validators)?).await
+}
+
+/// Removes the `part` of a download that cannot be resumed, with its validators.
+async fn remove_part(part: &Path) -> Result<()> {
+    let _ = tokio::fs::remove_file(part_validators_file(part)).await;
+    tokio::fs::remove_file(part).await
+}
+
+/// The digests of `hash_names`, which fails on algorithms makepkg does not know, like a
+/// `foosums` array the bash extraction found.
+fn digests(hash_names: &[String]) -> Result<Digests> {
+    let mut digests = Digests::with_capacity(hash_names.len());
+    for hash in hash_names {
+        digests.push(match hash.as_str() {
+            "ck" => Box::new(Cksum::new()),
+            "md5" => Box::new(Md5::new()),
+            "sha1" => Box::new(Sha1::new()),
+            "sha224" => Box::new(Sha224::new()),
+            "sha256" => Box::new(Sha256::new()),
+            "sha384" => Box::new(Sha384::new()),
+            "sha512" => Box::new(Sha512::new()),
+            "sha3_256" => Box::new(Sha3_256::new()),
+            "sha3_512" => Box::new(Sha3_512::new()),
+            "b2" => Box::new(Blake2b::new()),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unsupported checksum algorithm of {}sums", hash),
+                ))
+            }
+        });
+    }
+    Ok(digests)
+}
+
+fn sums(source: &Source, hash_names: &[String], digests: Digests) -> Vec<String> {
+    hash_names
+        .iter()
+        .zip(digests)
+        .map(|(hash_name, digest)| {
+            if source.is_signature() {
+                "SKIP".to_string()
+            } else {
+                encode_sum(hash_name, &digest.result())
+            }
+        })
+        .collect()
+}
+
+/// Whether `sum` is an actual sum rather than `SKIP`, a placeholder or missing.
+fn is_sum(sum: &str) -> bool {
+    !sum.is_empty() && sum != "SKIP" && sum != PLACEHOLDER_SUM
+}
+
+/// Formats a digest the way makepkg writes it: hex, except for `cksum`'s decimal CRC.
+fn encode_sum(hash_name: &str, sum: &[u8]) -> String {
+    match (hash_name, sum) {
+        ("ck", &[a, b, c, d]) => u32::from_be_bytes([a, b, c, d]).to_string(),
+        _ => hex::encode(sum),
+    }
+}
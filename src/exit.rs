@@ -0,0 +1,63 @@
+//! The exit codes of pkgbump, for scripts to tell its outcomes apart.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::Error;
+
+/// The PKGBUILD was updated, or a subcommand succeeded.
+pub const UPDATED: i32 = 0;
+/// Any failure without a code of its own, like invalid arguments or an unparsable PKGBUILD.
+pub const FAILED: i32 = 1;
+/// The PKGBUILD is already up to date, bumping it changed nothing.
+pub const UNCHANGED: i32 = 2;
+/// A source could not be downloaded.
+pub const DOWNLOAD_FAILED: i32 = 3;
+/// A source does not match its sums, upstream's checksums, its signature or is a broken archive.
+pub const VERIFICATION_FAILED: i32 = 4;
+
+/// An error with its exit code, carried inside an `io::Error` of the same kind so that it goes
+/// through `?` like any other.
+#[derive(Debug)]
+struct Classified {
+    code: i32,
+    error: Error,
+}
+
+impl fmt::Display for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl StdError for Classified {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Marks `error` as a download failure.
+pub fn download(error: Error) -> Error {
+    classify(error, DOWNLOAD_FAILED)
+}
+
+/// Marks `error` as a verification failure.
+pub fn verification(error: Error) -> Error {
+    classify(error, VERIFICATION_FAILED)
+}
+
+/// Errors keep the first code they are given, which is the most specific one.
+fn classify(error: Error, code: i32) -> Error {
+    if classified(&error).is_some() {
+        return error;
+    }
+    Error::new(error.kind(), Classified { code, error })
+}
+
+fn classified(error: &Error) -> Option<&Classified> {
+    error.get_ref()?.downcast_ref()
+}
+
+/// The code to exit with on `error`.
+pub fn code(error: &Error) -> i32 {
+    classified(error).map_or(FAILED, |classified| classified.code)
+}
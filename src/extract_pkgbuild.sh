@@ -1,22 +1,57 @@
 #!/usr/bin/bash
 
-. /usr/share/makepkg/util.sh
-. /usr/share/makepkg/integrity.sh
+. /usr/share/makepkg/util.sh || exit
+. /usr/share/makepkg/integrity.sh || exit
 
-known_hash_algos=('md5' 'sha1' 'sha224' 'sha256' 'sha384' 'sha512')
+known_hash_algos=('ck' 'md5' 'sha1' 'sha224' 'sha256' 'sha384' 'sha512' 'sha3_256' 'sha3_512' 'b2')
+
+print_sources() {
+    echo -n '['
+    local comma=false
+    for source in "$@"; do
+        if [[ $comma == "true" ]]; then
+            echo -n ','
+        fi
+        comma=true
+        echo -n '{'
+        if [[ $source == *::* ]]; then
+            echo -n "\"rename\":\"${source%%::*}\","
+        fi
+        echo -n "\"filename\":\"$(get_filename "$source")\",\"url\":\"$(get_url "$source")\"}"
+    done
+    echo -n ']'
+}
 
 . /dev/stdin
-echo -n '{"sources":['
+echo -n "{\"pkgbase\":\"${pkgbase:-${pkgname[0]}}\",\"pkgnames\":["
+comma=false
+for name in "${pkgname[@]}"; do
+    if [[ $comma == "true" ]]; then
+        echo -n ','
+    fi
+    comma=true
+    echo -n "\"$name\""
+done
+
+echo -n '],"sources":'
+print_sources "${source[@]}"
+
+echo -n ',"arch_sources":{'
 comma=false
-for source in "${source[@]}"; do
+for a in "${arch[@]}"; do
+    array_name="source_${a}[@]"
+    if [[ -z ${!array_name} ]]; then
+        continue
+    fi
     if [[ $comma == "true" ]]; then
         echo -n ','
     fi
     comma=true
-    echo -n "{\"filename\":\"$(get_filename "$source")\",\"url\":\"$(get_url "$source")\"}"
+    echo -n "\"$a\":"
+    print_sources "${!array_name}"
 done
 
-echo -n '],"hashes":['
+echo -n '},"hashes":['
 comma=false
 for integ in $(get_integlist); do
     if [[ $comma == "true" ]]; then
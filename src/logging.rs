@@ -0,0 +1,91 @@
+//! Messages on stderr, logged with tracing and filtered by `-q`, `-v` and `-vv`. The download
+//! progress bars are cleared while a message is printed, so that it does not garble them.
+
+use indicatif::MultiProgress;
+use std::fmt::{self, Write};
+use std::process::Command;
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{debug, Event, Level, Metadata, Subscriber};
+
+/// The progress bars on the terminal, if any.
+static BARS: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+struct Logger {
+    level: Level,
+}
+
+/// Prints the messages of `level` and the more severe ones from now on.
+pub fn init(level: Level) {
+    // Only fails if it was called before, and then the first level stays.
+    let _ = tracing::subscriber::set_global_default(Logger { level });
+}
+
+/// Prints messages above `bars` from now on, or plainly without any.
+pub fn set_bars(bars: Option<MultiProgress>) {
+    *BARS.lock().unwrap() = bars;
+}
+
+/// Logs that `command` is about to run, and returns it to be run.
+pub fn command(command: &mut Command) -> &mut Command {
+    debug!("running {:?}", command);
+    command
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Only pkgbump's own, which are not drowned out by those of the HTTP client.
+        *metadata.level() <= self.level && metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(LevelFilter::from_level(self.level))
+    }
+
+    fn new_span(&self, _: &Attributes) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, _: &Record) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event) {
+        let level = *event.metadata().level();
+        let mut line = String::from(if level == Level::ERROR {
+            "Error: "
+        } else if level == Level::WARN {
+            "Warning: "
+        } else if level == Level::INFO {
+            ""
+        } else if level == Level::DEBUG {
+            "debug: "
+        } else {
+            "trace: "
+        });
+        event.record(&mut Line(&mut line));
+        match &*BARS.lock().unwrap() {
+            Some(bars) => bars.suspend(|| eprintln!("{}", line)),
+            None => eprintln!("{}", line),
+        }
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+/// Writes the message of an event, followed by its other fields as `name=value`.
+struct Line<'a>(&'a mut String);
+
+impl Visit for Line<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let _ = if field.name() == "message" {
+            write!(self.0, "{:?}", value)
+        } else {
+            write!(self.0, " {}={:?}", field.name(), value)
+        };
+    }
+}
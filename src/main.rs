@@ -1,82 +1,498 @@
-use digest::{Digest, DynDigest};
-use md5::Md5;
-use regex::{Captures, Regex};
+use config::{Config, UrlRewrite, VariableRule, WriteConfig};
+use download::Downloader;
+use makepkg::MakepkgConf;
+use pkgbuild::{Layout, Pkgbuild};
 use serde::Deserialize;
-use sha1::Sha1;
-use sha2::{Sha224, Sha256, Sha384, Sha512};
-use std::borrow::Cow;
-use std::fs::File;
-use std::io::{Read, Result, Write};
-use std::mem::MaybeUninit;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io::{Error, ErrorKind, IsTerminal, Result, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 use tempfile::{NamedTempFile, TempPath};
+use tracing::{error, info, warn, Level};
 
+mod accel;
+mod archive;
+mod cache;
+mod checksums;
+mod cksum;
+mod config;
+mod cookies;
+mod diff;
+mod download;
+mod exit;
+mod logging;
+mod makepkg;
+mod netrc;
+mod parser;
+mod pgp;
+mod pin;
+mod pkgbuild;
+mod progress;
+mod report;
+mod signify;
+mod srcdiff;
+mod srcinfo;
+mod validate;
+mod vcs;
+mod version;
+
+/// Ordered from weakest to strongest.
+const KNOWN_HASH_ALGOS: &[&str] = &[
+    "ck", "md5", "sha1", "sha224", "sha256", "sha384", "sha512", "sha3_256", "sha3_512", "b2",
+];
+const SIGNATURE_EXTENSIONS: &[&str] = &[".sig", ".sign", ".asc"];
+/// Signatures of minisign, which makepkg does not know and checks by their sums.
+const MINISIGN_EXTENSION: &str = ".minisig";
+/// Arrays of package relations that may pin the package's own version.
+const DEPENDENCY_ARRAYS: &[&str] = &[
+    "provides",
+    "conflicts",
+    "replaces",
+    "depends",
+    "makedepends",
+    "checkdepends",
+    "optdepends",
+];
+
+/// Used for new sums arrays when the PKGBUILD has none, like makepkg's `INTEGRITY_CHECK`.
+const DEFAULT_HASH_ALGO: &str = "sha256";
 const EXTRACT_PKGBUILD_SCRIPT: &[u8] = include_bytes!("extract_pkgbuild.sh");
+const RUN_PKGVER_SCRIPT: &str = r#"
+source ./PKGBUILD || exit
+declare -F pkgver >/dev/null || { echo "PKGBUILD has no pkgver() function" >&2; exit 1; }
+cd "$srcdir" && pkgver
+"#;
 
 #[derive(Debug, structopt::StructOpt)]
-#[structopt(about)]
+#[structopt(about, setting = structopt::clap::AppSettings::SubcommandsNegateReqs)]
 struct Opt {
-    new_version: String,
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+    /// Print only warnings and errors
+    #[structopt(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Also print debug messages, like the HTTP requests and commands run, or with -vv everything
+    #[structopt(short, long, global = true, parse(from_occurrences))]
+    verbose: u8,
+    #[structopt(required_unless_one = &["from-pkgver", "update-sums"])]
+    new_version: Option<String>,
+    /// Fetch the VCS sources and take the new version from the PKGBUILD's pkgver() function
+    #[structopt(long, conflicts_with = "new-version")]
+    from_pkgver: bool,
+    /// Extract metadata by sourcing the PKGBUILD with bash instead of parsing it natively
+    #[structopt(long)]
+    bash: bool,
+    /// Only download and update the architecture-specific sources of this architecture
+    #[structopt(long = "arch", value_name = "arch", number_of_values = 1)]
+    arches: Vec<String>,
+    /// Update the PKGBUILD in place instead of printing it
+    #[structopt(short, long, global = true)]
+    write: bool,
+    /// Do not keep a copy of the PKGBUILD that --write replaces
+    #[structopt(long, global = true)]
+    no_backup: bool,
+    /// Do not update .SRCINFO along with the PKGBUILD that --write replaces
+    #[structopt(long, global = true)]
+    no_srcinfo: bool,
+    /// Print the changes to the PKGBUILD as a unified diff instead of all of it
+    #[structopt(long, global = true)]
+    diff: bool,
+    /// Whether to color the diff
+    #[structopt(
+        long,
+        global = true,
+        value_name = "when",
+        possible_values = &["auto", "always", "never"],
+        default_value = "auto"
+    )]
+    color: String,
+    /// Print a JSON report of the bump to stdout when done, with the other messages on stderr
+    #[structopt(
+        long,
+        value_name = "format",
+        possible_values = &["text", "json"],
+        default_value = "text"
+    )]
+    output: String,
+    /// Do not reset pkgrel to 1 when the version changes
+    #[structopt(long)]
+    keep_pkgrel: bool,
+    /// Set pkgrel to this release number instead of resetting it to 1
+    #[structopt(long, conflicts_with = "keep-pkgrel", parse(try_from_str = parse_pkgrel))]
+    pkgrel: Option<String>,
+    /// Set epoch to this value
+    #[structopt(long)]
+    epoch: Option<u32>,
+    /// Increment epoch, for when upstream's new version sorts before the current one
+    #[structopt(long, conflicts_with = "epoch")]
+    bump_epoch: bool,
+    /// Allow setting a version that sorts before the current one
+    #[structopt(long)]
+    allow_downgrade: bool,
+    /// Download the sources and rewrite the sums even if the PKGBUILD already has the version
+    #[structopt(long)]
+    force: bool,
+    /// Also set a top-level variable, the value is inserted verbatim as shell text
+    #[structopt(
+        long = "set",
+        value_name = "KEY=VALUE",
+        number_of_values = 1,
+        parse(try_from_str = parse_set)
+    )]
+    sets: Vec<(String, String)>,
+    /// Point `_commit` at the commit of the new version's tag in the pinned git source
+    #[structopt(long)]
+    resolve_commit: bool,
+    /// Replace the existing sums arrays with ones of this algorithm, e.g. md5 -> sha256
+    #[structopt(long, value_name = "algo", parse(try_from_str = parse_hash_algo))]
+    migrate_sums: Option<String>,
+    /// Check the new sums against the checksum file upstream publishes at this URL, which may
+    /// use the placeholders of variables, or `auto` to look for them next to the sources
+    #[structopt(long = "checksums", value_name = "url", number_of_values = 1)]
+    checksums: Vec<String>,
+    /// Verify minisign and signify signatures of the sources against this public key
+    #[structopt(long = "signing-key", value_name = "key", number_of_values = 1)]
+    signing_keys: Vec<String>,
+    /// Check that downloaded archives decompress and list cleanly
+    #[structopt(long)]
+    check_archives: bool,
+    /// Summarize how each new source archive differs from the previous version's
+    #[structopt(long)]
+    diff_sources: bool,
+    /// Add the key with this fingerprint to validpgpkeys after confirming its user IDs
+    #[structopt(long = "add-pgp-key", value_name = "fingerprint", number_of_values = 1)]
+    add_pgp_keys: Vec<String>,
+    /// Remove the key with this fingerprint from validpgpkeys
+    #[structopt(
+        long = "remove-pgp-key",
+        value_name = "fingerprint",
+        number_of_values = 1
+    )]
+    remove_pgp_keys: Vec<String>,
+    /// Add the detached signature upstream publishes next to each source that lacks one
+    #[structopt(long)]
+    add_signatures: bool,
+    /// Remove sums arrays without matching sources and those of weaker algorithms than another
+    /// array of the same sources
+    #[structopt(long)]
+    prune_sums: bool,
+    /// Lay out sums arrays this way instead of like the arrays they replace
+    #[structopt(long, value_name = "style", possible_values = &["inline", "per-line"])]
+    sums_style: Option<String>,
+    /// Put each sum on a line of its own indented by this many spaces, implies
+    /// `--sums-style per-line`
+    #[structopt(long, value_name = "width")]
+    sums_indent: Option<usize>,
+    /// Tag to resolve with --resolve-commit, by default v<version> or <version>
+    #[structopt(long, requires = "resolve-commit")]
+    tag: Option<String>,
+    /// Append a dated entry for the new version to the file named by `changelog`
+    #[structopt(long)]
+    changelog: bool,
+    /// Print what would change without downloading, hashing or writing anything, only checking
+    /// that the remote sources exist
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "from-pkgver", "write", "changelog", "checksums", "diff-sources", "check-archives",
+        ]
+    )]
+    dry_run: bool,
+    /// Only bump the version, writing placeholder sums for the remote sources instead of
+    /// downloading them
+    #[structopt(
+        long,
+        conflicts_with_all = &["from-pkgver", "resolve-commit", "add-pgp-key", "add-signatures"]
+    )]
+    no_download: bool,
+    /// Set every sum to SKIP instead of downloading the sources, for packages whose integrity
+    /// comes from signed VCS tags
+    #[structopt(long, conflicts_with = "no-download")]
+    skip_sums: bool,
+    /// Never access the network, taking the sources from SRCDEST, the download cache or the
+    /// package directory, and fail with a list of those that are missing
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "from-pkgver", "resolve-commit", "add-pgp-key", "add-signatures", "checksums",
+        ]
+    )]
+    offline: bool,
+    /// Download up to this many sources at a time
+    #[structopt(short, long, value_name = "n", default_value = "4")]
+    jobs: usize,
+    /// Retry failed downloads this many times, overriding the configuration
+    #[structopt(long, value_name = "n")]
+    retries: Option<u32>,
+    /// Seconds to wait for a connection, overriding the configuration
+    #[structopt(long, value_name = "seconds")]
+    connect_timeout: Option<f64>,
+    /// Seconds to wait for more data during a download, overriding the configuration
+    #[structopt(long, value_name = "seconds")]
+    read_timeout: Option<f64>,
+    /// Follow at most this many redirects for one request, overriding the configuration
+    #[structopt(long, value_name = "n")]
+    max_redirects: Option<usize>,
+    /// Send requests with this User-Agent, overriding the configuration
+    #[structopt(long, value_name = "agent")]
+    user_agent: Option<String>,
+    /// Refuse to download sources over plain HTTP or FTP, except from the configured http_hosts
+    #[structopt(long)]
+    require_https: bool,
+    /// Only connect to servers over IPv4
+    #[structopt(long, conflicts_with = "ipv6")]
+    ipv4: bool,
+    /// Only connect to servers over IPv6
+    #[structopt(long)]
+    ipv6: bool,
+    /// Send all requests through this HTTP or SOCKS proxy, e.g. socks5h://localhost:9050
+    #[structopt(long, value_name = "url")]
+    proxy: Option<String>,
+    /// Download the other sources when one fails, and list the failures at the end
+    #[structopt(long)]
+    keep_going: bool,
+    /// Download every remote source with makepkg.conf's DLAGENTS instead of natively
+    #[structopt(long)]
+    dlagents: bool,
+    /// Hand HTTP(S) transfers to curl or aria2c instead of the builtin client
+    #[structopt(
+        long,
+        value_name = "tool",
+        possible_values = &["builtin", "curl", "aria2c"],
+        conflicts_with = "dlagents"
+    )]
+    downloader: Option<String>,
+    /// Keep the version and only download the current sources again to rewrite the sums
+    /// arrays, like updpkgsums
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "new-version", "from-pkgver", "epoch", "bump-epoch", "pkgrel", "resolve-commit",
+            "changelog", "no-download", "skip-sums",
+        ]
+    )]
+    update_sums: bool,
+    /// Limit all downloads together to this many bytes per second, e.g. 500K or 2M
+    #[structopt(long, value_name = "rate")]
+    limit_rate: Option<String>,
+    /// Limit each download to this many bytes per second
+    #[structopt(long, value_name = "rate")]
+    limit_rate_per_source: Option<String>,
+    /// Download large sources over up to this many connections at once
+    #[structopt(long, value_name = "n")]
+    segments: Option<u32>,
+    /// Do not check that all sources exist before downloading any
+    #[structopt(long)]
+    no_preflight: bool,
+    /// Send the cookies in this Netscape cookie jar, e.g. to accept a license
+    #[structopt(long, value_name = "file")]
+    cookie_jar: Option<String>,
+    /// Fail any source larger than this, e.g. 2G, overriding the configuration
+    #[structopt(long, value_name = "size")]
+    max_size: Option<String>,
+    /// Read and write files this many bytes at a time, e.g. 1M
+    #[structopt(long, value_name = "size")]
+    buffer_size: Option<String>,
+    /// Spread the sums algorithms of each download over at most this many threads, instead of one
+    /// thread per algorithm; a single algorithm always hashes on one thread
+    #[structopt(long, value_name = "n")]
+    hash_threads: Option<usize>,
+    /// Only hash remote sources as they download, without writing them to disk
+    #[structopt(long, conflicts_with = "keep-sources")]
+    no_keep: bool,
+    /// Write downloaded sources to disk even if `keep_sources = false` is configured
+    #[structopt(long)]
+    keep_sources: bool,
+    /// Download every source again instead of reusing the copies in the download cache
+    #[structopt(long)]
+    no_cache: bool,
+    /// Text of the changelog entry
+    #[structopt(long, value_name = "message", default_value = "upstream release")]
+    changelog_message: String,
 }
 
-#[derive(Debug)]
-struct Pkgbuild {
-    content: String,
-    regex: Regex,
+fn parse_set(set: &str) -> std::result::Result<(String, String), String> {
+    match set.split_once('=') {
+        Some((key, value))
+            if key.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic())
+                && key.chars().all(|c| c == '_' || c.is_ascii_alphanumeric()) =>
+        {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got `{}`", set)),
+    }
 }
 
-impl Pkgbuild {
-    fn new() -> Result<Pkgbuild> {
-        Ok(Pkgbuild {
-            content: std::fs::read_to_string("PKGBUILD")?,
-            regex: Regex::new(r"(.+)=(\([^\)]+\)|.+)").unwrap(),
-        })
+fn parse_hash_algo(algo: &str) -> std::result::Result<String, String> {
+    if KNOWN_HASH_ALGOS.contains(&algo) {
+        Ok(algo.to_string())
+    } else {
+        Err(format!(
+            "unsupported algorithm `{}`, expected one of {}",
+            algo,
+            KNOWN_HASH_ALGOS.join(", ")
+        ))
     }
+}
 
-    fn set(&mut self, k: &str, v: &str) {
-        match self.regex.replace_all(&self.content, |caps: &Captures| {
-            format!("{}={}", &caps[1], if &caps[1] == k { v } else { &caps[2] })
-        }) {
-            Cow::Borrowed(_) => (),
-            Cow::Owned(o) => self.content = o,
-        }
-    }
+#[derive(Debug, structopt::StructOpt)]
+enum Subcommand {
+    /// Increment pkgrel to rebuild the current version, leaving the sources alone
+    Rebuild,
+    /// Check the current sources against the sums arrays without changing anything
+    Verify,
 }
 
-impl AsRef<[u8]> for Pkgbuild {
-    fn as_ref(&self) -> &[u8] {
-        self.content.as_bytes()
+fn parse_pkgrel(pkgrel: &str) -> std::result::Result<String, String> {
+    let valid = pkgrel.split('.').count() <= 2
+        && pkgrel.split('.').all(|part| {
+            !part.is_empty() && !part.starts_with('0') && part.bytes().all(|b| b.is_ascii_digit())
+        });
+    if valid {
+        Ok(pkgrel.to_string())
+    } else {
+        Err(format!("invalid pkgrel `{}`", pkgrel))
     }
 }
 
 #[derive(Debug, Deserialize)]
 struct Source {
+    /// Explicit name of `name::url` entries.
+    #[serde(default)]
+    rename: Option<String>,
     filename: String,
     url: String,
 }
 
+impl Source {
+    fn new(entry: &str) -> Source {
+        let (rename, url) = match entry.split_once("::") {
+            Some((rename, url)) => (Some(rename.to_string()), url),
+            None => (None, entry),
+        };
+        let filename = entry.split("::").next().unwrap();
+        let filename = filename.rsplit('/').next().unwrap();
+        let filename = filename.split('#').next().unwrap();
+        let filename = filename.split('?').next().unwrap();
+        let protocol = protocol(entry);
+        let filename = if vcs::PROTOCOLS.contains(&protocol) {
+            let filename = filename.strip_suffix('/').unwrap_or(filename);
+            match protocol {
+                "bzr" => filename
+                    .split_once("lp:")
+                    .map_or(filename, |(_, name)| name)
+                    .to_string(),
+                "fossil" => format!("{}.fossil", filename),
+                "git" => filename.split(".git").next().unwrap().to_string(),
+                _ => filename.to_string(),
+            }
+        } else {
+            filename.to_string()
+        };
+        Source {
+            rename,
+            filename,
+            url: url.to_string(),
+        }
+    }
+
+    fn is_vcs(&self) -> bool {
+        vcs::PROTOCOLS.contains(&protocol(&self.url))
+    }
+
+    /// Detached signatures, which makepkg verifies with gpg rather than by checksum.
+    fn is_signature(&self) -> bool {
+        SIGNATURE_EXTENSIONS
+            .iter()
+            .any(|extension| self.filename.ends_with(extension))
+    }
+
+    /// Files shipped alongside the PKGBUILD, like patches and install scripts.
+    fn is_local(&self) -> bool {
+        protocol(&self.url) == "local"
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.rename {
+            Some(rename) => write!(f, "{}::{}", rename, self.url),
+            None => write!(f, "{}", self.url),
+        }
+    }
+}
+
+fn protocol(entry: &str) -> &str {
+    let url = entry.split_once("::").map_or(entry, |(_, url)| url);
+    if let Some((protocol, _)) = url.split_once("://") {
+        protocol.split('+').next().unwrap()
+    } else if let Some((protocol, _)) = url.split_once("lp:") {
+        protocol
+    } else {
+        "local"
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct Metadata {
+    #[serde(default)]
+    pkgbase: String,
+    #[serde(default)]
+    pkgnames: Vec<String>,
     sources: Vec<Source>,
+    #[serde(default)]
+    arch_sources: BTreeMap<String, Vec<Source>>,
     hashes: Vec<String>,
 }
 
 impl Metadata {
-    fn digests(&self) -> Vec<Box<dyn DynDigest>> {
-        let mut digests = Vec::<Box<dyn DynDigest>>::with_capacity(self.hashes.len());
-        for hash in &self.hashes {
-            digests.push(match hash.as_str() {
-                "md5" => Box::new(Md5::new()),
-                "sha1" => Box::new(Sha1::new()),
-                "sha224" => Box::new(Sha224::new()),
-                "sha256" => Box::new(Sha256::new()),
-                "sha384" => Box::new(Sha384::new()),
-                "sha512" => Box::new(Sha512::new()),
-                _ => panic!("Unsupported hash {}", hash),
-            });
+    fn parse(pkgbuild: &Pkgbuild) -> Result<Metadata> {
+        let parsed = parser::parse(&pkgbuild.content)?;
+        let sources = |name: &str| -> Result<Vec<Source>> {
+            Ok(parsed
+                .elements(name)?
+                .iter()
+                .map(|entry| Source::new(entry))
+                .collect())
+        };
+        let arches = parsed.array("arch")?;
+        let mut arch_sources = BTreeMap::new();
+        for arch in &arches {
+            let sources = sources(&format!("source_{}", arch))?;
+            if !sources.is_empty() {
+                arch_sources.insert(arch.clone(), sources);
+            }
+        }
+        let mut hashes = Vec::new();
+        for algo in KNOWN_HASH_ALGOS {
+            let mut names = vec![format!("{}sums", algo)];
+            names.extend(arches.iter().map(|arch| format!("{}sums_{}", algo, arch)));
+            for name in names {
+                if parsed.elements(&name)?.iter().any(|sum| !sum.is_empty()) {
+                    hashes.push(algo.to_string());
+                    break;
+                }
+            }
+        }
+        if hashes.is_empty() {
+            hashes.push(DEFAULT_HASH_ALGO.to_string());
         }
-        digests
+        let pkgnames = parsed.array("pkgname")?;
+        let pkgbase = match parsed.scalar("pkgbase")? {
+            Some(pkgbase) => pkgbase,
+            None => pkgnames.first().cloned().unwrap_or_default(),
+        };
+        Ok(Metadata {
+            pkgbase,
+            pkgnames,
+            sources: sources("source")?,
+            arch_sources,
+            hashes,
+        })
     }
 }
 
@@ -95,79 +511,1573 @@ impl ExtractPkgbuild {
 
     fn run<T: AsRef<[u8]>>(&self, input: T) -> Result<Metadata> {
         let input = input.as_ref();
-        let mut child = Command::new("bash")
-            .arg(&self.script)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
+        let mut child = logging::command(
+            Command::new("bash")
+                .arg(&self.script)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .spawn()?;
         {
             let stdin = child.stdin.as_mut().unwrap();
             stdin.write_all(input)?;
         }
         let output = child.wait_with_output()?;
-        Ok(serde_json::from_slice(&output.stdout).unwrap())
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            return Err(Error::other(format!(
+                "sourcing the PKGBUILD with bash failed: {}",
+                stderr.trim()
+            )));
+        }
+        serde_json::from_slice(&output.stdout).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "cannot read what bash extracted from the PKGBUILD ({}): {}",
+                    e,
+                    stderr.trim()
+                ),
+            )
+        })
     }
 }
 
-fn run(opt: Opt) -> Result<()> {
-    let mut pkgbuild = Pkgbuild::new()?;
-    pkgbuild.set("pkgver", &opt.new_version);
-    let metadata = ExtractPkgbuild::new()?.run(&pkgbuild)?;
-    let mut digests = metadata.digests();
-    let mut digest_hashes: Vec<Vec<String>> = vec![Vec::new(); digests.len()];
-    for source in &metadata.sources {
-        println!("{} -> {}", source.url, source.filename);
-        let mut response = reqwest::get(&source.url)
-            .unwrap()
-            .error_for_status()
-            .unwrap();
-
-        let mut file = File::create(&source.filename)?;
-        let mut buf = MaybeUninit::<[u8; 8 * 1024]>::uninit();
-        loop {
-            let len = match response.read(unsafe {
-                std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, 8 * 1024)
-            }) {
-                Ok(0) => break,
-                Ok(len) => len,
-                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
-                Err(e) => return Err(e),
+/// Checks out the VCS sources into `src/` and runs `pkgver()` there, like makepkg does after
+/// extracting the sources.
+fn run_pkgver<'a>(
+    downloader: &Downloader,
+    sources: impl IntoIterator<Item = &'a Source>,
+) -> Result<String> {
+    let startdir = std::env::current_dir()?;
+    let srcdir = startdir.join("src");
+    std::fs::create_dir_all(&srcdir)?;
+    for source in sources.into_iter().filter(|source| source.is_vcs()) {
+        downloader.check_host(&source.url)?;
+        info!("{} -> src/{} (checkout)", source.url, source.filename);
+        vcs::checkout(&source.url, &srcdir.join(&source.filename))?;
+    }
+    let output = logging::command(
+        Command::new("bash")
+            .args(["-c", RUN_PKGVER_SCRIPT])
+            .env("startdir", &startdir)
+            .env("srcdir", &srcdir)
+            .stderr(Stdio::inherit()),
+    )
+    .output()?;
+    if !output.status.success() {
+        return Err(Error::other("pkgver() failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Applies the rewrites that found moved sources to the entries of `key`, whose elements must
+/// be the sources one to one.
+fn relocate_sources(
+    pkgbuild: &mut Pkgbuild,
+    key: &str,
+    sources: &[Source],
+    moved: &[(usize, &UrlRewrite)],
+) -> Result<()> {
+    let mut count = 0;
+    pkgbuild.rewrite_elements(
+        |name| name == key,
+        |_| {
+            count += 1;
+            None
+        },
+    )?;
+    if count != sources.len() {
+        for (i, _) in moved {
+            warn!(
+                "{} moved but {} is not a literal array, update it by hand",
+                sources[*i], key
+            );
+        }
+        return Ok(());
+    }
+    // Elements are rewritten from the last one.
+    let mut index = count;
+    pkgbuild.rewrite_elements(
+        |name| name == key,
+        |entry| {
+            index -= 1;
+            let (_, rewrite) = moved.iter().find(|(moved, _)| *moved == index)?;
+            let quote = &entry[..entry.len() - entry.trim_start_matches(['"', '\'']).len()];
+            let unquoted = &entry[quote.len()..entry.len() - quote.len()];
+            let (rename, url) = match unquoted.split_once("::") {
+                Some((rename, url)) => (format!("{}::", rename), url),
+                None => (String::new(), unquoted),
+            };
+            match rewrite.apply(url) {
+                Some(url) => Some(format!("{}{}{}{}", quote, rename, url, quote)),
+                None => {
+                    warn!(
+                        "{} moved but its entry in {} does not match, update it by hand",
+                        sources[index], key
+                    );
+                    None
+                }
+            }
+        },
+    )
+}
+
+/// Reads the current `<algo>sums<suffix>` arrays, in the order of `hash_names`.
+fn old_sums(pkgbuild: &Pkgbuild, hash_names: &[String], suffix: &str) -> Result<Vec<Vec<String>>> {
+    hash_names
+        .iter()
+        .map(|hash_name| pkgbuild.elements(&format!("{}sums{}", hash_name, suffix)))
+        .collect()
+}
+
+/// Finds sums arrays that do not belong to any source array, and with `duplicates` also those
+/// made redundant by a stronger algorithm for the same sources.
+fn stale_sums(pkgbuild: &Pkgbuild, duplicates: bool) -> Result<Vec<(String, String)>> {
+    let parsed = parser::parse(&pkgbuild.content)?;
+    let arches = parsed.array("arch")?;
+    let mut names: Vec<&str> = parsed
+        .assignments()
+        .iter()
+        .map(|assignment| assignment.name.as_str())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    let mut by_suffix: BTreeMap<&str, Vec<(usize, &str)>> = BTreeMap::new();
+    for name in names {
+        let (algo, suffix) = match name.find("sums") {
+            Some(i) => (&name[..i], &name[i + 4..]),
+            None => continue,
+        };
+        if let Some(strength) = KNOWN_HASH_ALGOS.iter().position(|known| *known == algo) {
+            if suffix.is_empty() || suffix.starts_with('_') {
+                by_suffix.entry(suffix).or_default().push((strength, name));
+            }
+        }
+    }
+
+    let mut stale = Vec::new();
+    for (suffix, mut hashsums) in by_suffix {
+        let sources = parsed.elements(&format!("source{}", suffix))?.len();
+        let orphaned = sources == 0
+            || suffix
+                .strip_prefix('_')
+                .is_some_and(|arch| !arches.iter().any(|known| known == arch));
+        hashsums.sort_unstable();
+        let strongest = hashsums.last().map(|(_, name)| name.to_string());
+        for (_, hashsum) in hashsums {
+            let reason = if orphaned {
+                format!("has no matching source{} array", suffix)
+            } else if duplicates && strongest.as_deref() != Some(hashsum) {
+                let sums = parsed.elements(hashsum)?.len();
+                if sums == sources {
+                    format!("duplicates {}", strongest.as_deref().unwrap())
+                } else {
+                    format!(
+                        "duplicates {} and is out of sync with {} sums for {} sources",
+                        strongest.as_deref().unwrap(),
+                        sums,
+                        sources
+                    )
+                }
+            } else {
+                continue;
             };
-            let buf_read = unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, len) };
-            file.write_all(&buf_read)?;
-            for digest in &mut digests {
-                digest.input(&buf_read);
+            stale.push((hashsum.to_string(), reason));
+        }
+    }
+    Ok(stale)
+}
+
+/// Merges the sums arrays of several algorithms into one that only keeps the positions that
+/// are `SKIP` in all of them.
+fn skipped_sums(old_sums: &[Vec<String>]) -> Vec<String> {
+    let len = old_sums.iter().map(Vec::len).max().unwrap_or(0);
+    (0..len)
+        .map(|i| {
+            if old_sums
+                .iter()
+                .all(|sums| sums.get(i).map(String::as_str) == Some("SKIP"))
+            {
+                "SKIP".to_string()
+            } else {
+                String::new()
+            }
+        })
+        .collect()
+}
+
+fn set_sums(
+    pkgbuild: &mut Pkgbuild,
+    hash_names: &[String],
+    suffix: &str,
+    old_sums: &[Vec<String>],
+    digest_hashes: Vec<Vec<String>>,
+    layout: Option<Layout>,
+) -> Result<Vec<(String, Vec<String>)>> {
+    let mut arrays = Vec::new();
+    let mut anchor = format!("source{}", suffix);
+    let source_segments: Vec<usize> = pkgbuild.segments(&anchor)?.iter().map(Vec::len).collect();
+    // Every branch counts, so only sums built up in the same branches match the sources that run.
+    let conditional = source_segments.len() > 1 && pkgbuild.conditional(&anchor)?;
+    for ((hash_name, old), hashes) in hash_names.iter().zip(old_sums).zip(digest_hashes) {
+        let hashsum = format!("{}sums{}", hash_name, suffix);
+        let hashes: Vec<String> = hashes
+            .into_iter()
+            .enumerate()
+            .map(|(i, hash)| match old.get(i) {
+                Some(old) if old == "SKIP" => old.clone(),
+                _ => hash,
+            })
+            .collect();
+        let segments = pkgbuild.segments(&hashsum)?.len();
+        let aligned = source_segments.len() == segments
+            && source_segments.iter().sum::<usize>() == hashes.len();
+        if conditional && !aligned {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    concat!(
+                        "{} is assigned in branches that may not all run, but {} is not built ",
+                        "up in the same branches to match it"
+                    ),
+                    anchor, hashsum
+                ),
+            ));
+        }
+        if segments > 1 {
+            // Sums built up like the sources get the hashes of the matching source statements.
+            let mut chunks = vec![Vec::new(); segments];
+            if aligned {
+                let mut rest = hashes.as_slice();
+                for (chunk, len) in chunks.iter_mut().zip(&source_segments) {
+                    let (head, tail) = rest.split_at(*len);
+                    *chunk = head.to_vec();
+                    rest = tail;
+                }
+            } else {
+                warn!(
+                    "{} is not assigned like source{}, writing all sums to its first assignment",
+                    hashsum, suffix
+                );
+                chunks[0] = hashes.clone();
+            }
+            pkgbuild.set_array_segments(&hashsum, &chunks, layout)?;
+        } else if !pkgbuild.set_array(&hashsum, &hashes, layout)? {
+            if hashes.is_empty()
+                || !pkgbuild.insert_array_after(&anchor, &hashsum, &hashes, layout)?
+            {
+                continue;
+            }
+            anchor = hashsum.clone();
+        }
+        arrays.push((hashsum, hashes));
+    }
+    Ok(arrays)
+}
+
+/// Rewrites constraints on `old` in the relation arrays when they are about this package: any
+/// `provides` entry and entries naming one of the split packages. Other constraints on `old`
+/// and ones pinning a pkgrel are only reported.
+fn bump_dependencies(pkgbuild: &mut Pkgbuild, old: &str, new: &str) -> Result<()> {
+    let pkgnames = pkgbuild.array("pkgname")?;
+    let mut arrays = Vec::new();
+    for suffix in std::iter::once(String::new()).chain(
+        pkgbuild
+            .array("arch")?
+            .iter()
+            .map(|arch| format!("_{}", arch)),
+    ) {
+        arrays.extend(
+            DEPENDENCY_ARRAYS
+                .iter()
+                .map(|array| format!("{}{}", array, suffix)),
+        );
+    }
+    for array in &arrays {
+        let mut warnings = Vec::new();
+        pkgbuild.rewrite_elements(
+            |name| name == array,
+            |entry| {
+                let quote = match entry.chars().next() {
+                    Some(quote @ '"') | Some(quote @ '\'')
+                        if entry.len() > 1 && entry.ends_with(quote) =>
+                    {
+                        &entry[..1]
+                    }
+                    _ => "",
+                };
+                let dependency = &entry[quote.len()..entry.len() - quote.len()];
+                let (dependency, description) = match dependency.split_once(": ") {
+                    Some((dependency, description)) => (dependency, Some(description)),
+                    None => (dependency, None),
+                };
+                let (name, operator, version) = version::split_dependency(dependency)?;
+                let (epoch, pkgver, pkgrel) = version::split_evr(version);
+                if pkgver != old {
+                    return None;
+                }
+                let own =
+                    array.starts_with("provides") || pkgnames.iter().any(|pkgname| pkgname == name);
+                if !own || pkgrel.is_some() {
+                    warnings.push(dependency.to_string());
+                    return None;
+                }
+                let mut bumped = format!("{}{}{}", quote, name, operator);
+                if let Some(epoch) = epoch {
+                    bumped.push_str(&format!("{}:", epoch));
+                }
+                bumped.push_str(new);
+                if let Some(description) = description {
+                    bumped.push_str(&format!(": {}", description));
+                }
+                bumped.push_str(quote);
+                Some(bumped)
+            },
+        )?;
+        for dependency in warnings {
+            warn!(
+                "{} entry `{}` still refers to version {}",
+                array, dependency, old
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Appends `<date> <version>-<pkgrel>: <message>` to the PKGBUILD's changelog file.
+fn append_changelog(pkgbuild: &Pkgbuild, message: &str) -> Result<()> {
+    let path = pkgbuild.get("changelog")?.ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            "PKGBUILD has no `changelog` to add an entry to",
+        )
+    })?;
+    let pkgver = pkgbuild.get("pkgver")?.unwrap_or_default();
+    let version = version::full_version(pkgbuild.get("epoch")?.as_deref(), &pkgver);
+    let pkgrel = pkgbuild.get("pkgrel")?.unwrap_or_else(|| "1".to_string());
+    let mut content = match std::fs::read(&path) {
+        Ok(content) => content,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let newline: &[u8] = if content.windows(2).any(|pair| pair == b"\r\n") {
+        b"\r\n"
+    } else {
+        b"\n"
+    };
+    if !content.is_empty() && !content.ends_with(b"\n") {
+        content.extend_from_slice(newline);
+    }
+    content
+        .extend_from_slice(format!("{} {}-{}: {}", today(), version, pkgrel, message).as_bytes());
+    content.extend_from_slice(newline);
+    std::fs::write(&path, content)?;
+    info!("Added {}-{} to {}", version, pkgrel, path);
+    Ok(())
+}
+
+/// The current UTC date as `YYYY-MM-DD`.
+fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    // Howard Hinnant's days-to-civil conversion.
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Verifies the detached signatures among the downloaded `sources` against validpgpkeys, so the
+/// new sums never vouch for a file upstream did not sign.
+fn verify_signatures(
+    downloader: &Downloader,
+    pkgbuild: &Pkgbuild,
+    sources: &[Source],
+    signing_keys: &[String],
+) -> Result<()> {
+    let keys = pkgbuild
+        .array("validpgpkeys")?
+        .iter()
+        .map(|key| pgp::normalize_fingerprint(key))
+        .collect::<Result<Vec<_>>>()?;
+    if keys.is_empty() && signing_keys.is_empty() {
+        return Ok(());
+    }
+    let signatures = sources
+        .iter()
+        .filter(|source| source.is_signature() || source.filename.ends_with(MINISIGN_EXTENSION));
+    for signature in signatures {
+        let signed = sources.iter().find(|source| {
+            SIGNATURE_EXTENSIONS
+                .iter()
+                .chain(&[MINISIGN_EXTENSION])
+                .any(|extension| signature.filename == format!("{}{}", source.filename, extension))
+        });
+        let signed = match signed {
+            Some(signed) => signed,
+            None => continue,
+        };
+        let paths = downloader
+            .path(signature)
+            .zip(downloader.path(signed))
+            .filter(|(signature, signed)| signature.exists() && signed.exists());
+        let (signature_path, signed_path) = match paths {
+            Some(paths) => paths,
+            None => {
+                warn!(
+                    "cannot verify {} without the downloaded files",
+                    signature.filename
+                );
+                continue;
+            }
+        };
+        let key = if signify::is_signify(&std::fs::read(&signature_path)?) {
+            if signing_keys.is_empty() {
+                warn!("cannot verify {} without a signing key", signature.filename);
+                continue;
+            }
+            signify::verify(&signature_path, &signed_path, signing_keys)
+                .map(|id| format!("key {}", id))
+        } else if keys.is_empty() {
+            continue;
+        } else {
+            pgp::verify(
+                &signature_path,
+                &signed_path,
+                &keys,
+                Some(downloader).filter(|downloader| {
+                    !downloader.is_offline() && downloader.check_host(&pgp::keyserver()).is_ok()
+                }),
+            )
+        };
+        let key =
+            key.map_err(|e| Error::new(e.kind(), format!("{}: {}", signature.filename, e)))?;
+        info!("{}: good signature by {}", signed.filename, key);
+    }
+    Ok(())
+}
+
+/// Reads each downloaded archive through, failing on the first that is corrupt.
+fn check_archives(downloader: &Downloader, sources: &[Source]) -> Result<()> {
+    let archives = sources
+        .iter()
+        .filter(|source| !source.is_vcs() && archive::is_archive(&source.filename));
+    for source in archives {
+        let path = match downloader.path(source).filter(|path| path.exists()) {
+            Some(path) => path,
+            None => {
+                warn!(
+                    "cannot check {} without the downloaded file",
+                    source.filename
+                );
+                continue;
+            }
+        };
+        archive::check(&path, &source.filename)?;
+        info!("{}: archive is valid", source.filename);
+    }
+    Ok(())
+}
+
+/// Prints how each new archive among `sources` differs from the one at its position in
+/// `old_sources`, downloading the old one with its sums from the PKGBUILD unless it is kept.
+fn diff_sources(
+    downloader: &Downloader,
+    hash_names: &[String],
+    old_sums: &[Vec<String>],
+    old_sources: &[Source],
+    sources: &[Source],
+    jobs: usize,
+) {
+    for (i, source) in sources.iter().enumerate() {
+        let old = match old_sources.get(i) {
+            Some(old)
+                if old.url != source.url
+                    && !source.is_vcs()
+                    && archive::has_files(&source.filename)
+                    && archive::has_files(&old.filename) =>
+            {
+                old
+            }
+            _ => continue,
+        };
+        let new_path = match downloader.path(source).filter(|path| path.exists()) {
+            Some(path) if old.filename != source.filename => path,
+            _ => {
+                warn!(
+                    "cannot diff {} without keeping both versions",
+                    source.filename
+                );
+                continue;
+            }
+        };
+        let sums: Vec<Vec<String>> = old_sums
+            .iter()
+            .map(|sums| sums.get(i).cloned().into_iter().collect())
+            .collect();
+        let result = downloader
+            .download(
+                std::slice::from_ref(old),
+                &[true],
+                hash_names,
+                &sums,
+                &[],
+                true,
+                jobs,
+            )
+            .and_then(|(_, _, failed)| match failed.into_iter().next() {
+                Some((_, e)) => Err(e),
+                None => Ok(()),
+            })
+            .and_then(|()| {
+                let old_path = downloader
+                    .path(old)
+                    .filter(|path| path.exists())
+                    .ok_or_else(|| Error::new(ErrorKind::NotFound, "it was not downloaded"))?;
+                srcdiff::print(&old_path, &old.filename, &new_path, &source.filename)
+            });
+        if let Err(e) = result {
+            warn!(
+                "cannot diff {} against {}: {}",
+                source.filename, old.filename, e
+            );
+        }
+    }
+}
+
+/// Rewrites validpgpkeys with canonical fingerprints, removing and adding the given keys. New
+/// keys are fetched so their user IDs can be confirmed first.
+fn update_pgp_keys(
+    pkgbuild: &mut Pkgbuild,
+    downloader: &Downloader,
+    add: &[String],
+    remove: &[String],
+) -> Result<Vec<String>> {
+    let mut keys = pkgbuild
+        .array("validpgpkeys")?
+        .iter()
+        .map(|key| pgp::normalize_fingerprint(key))
+        .collect::<Result<Vec<_>>>()?;
+    for fingerprint in remove {
+        let fingerprint = pgp::normalize_fingerprint(fingerprint)?;
+        keys.retain(|key| *key != fingerprint);
+    }
+    let keyserver = downloader.check_host(&pgp::keyserver());
+    for fingerprint in add {
+        let fingerprint = pgp::normalize_fingerprint(fingerprint)?;
+        if keys.contains(&fingerprint) {
+            continue;
+        }
+        let uids = pgp::fetch_uids(&fingerprint, Some(downloader).filter(|_| keyserver.is_ok()))
+            .map_err(|e| match &keyserver {
+                Err(refused) => Error::new(
+                    refused.kind(),
+                    format!("{} is not in keys/pgp, and {}", fingerprint, refused),
+                ),
+                Ok(()) => e,
+            })?;
+        if !confirm(&format!(
+            "Add {} ({}) to validpgpkeys?",
+            fingerprint,
+            uids.join(", ")
+        ))? {
+            return Err(Error::new(
+                ErrorKind::Interrupted,
+                format!("key {} was not confirmed", fingerprint),
+            ));
+        }
+        keys.push(fingerprint);
+    }
+    if !pkgbuild.set_array("validpgpkeys", &keys, None)? && !keys.is_empty() {
+        let anchor = KNOWN_HASH_ALGOS
+            .iter()
+            .map(|algo| format!("{}sums", algo))
+            .rfind(|hashsum| pkgbuild.get(hashsum).ok().flatten().is_some())
+            .unwrap_or_else(|| "source".to_string());
+        pkgbuild.insert_array_after(&anchor, "validpgpkeys", &keys, None)?;
+    }
+    Ok(keys)
+}
+
+/// Adds a `.sig` or `.asc` source after each downloadable source without a signature, if
+/// upstream has one, with `SKIP` at the same position in the sums arrays.
+fn add_signatures(
+    pkgbuild: &mut Pkgbuild,
+    downloader: &Downloader,
+    suffix: &str,
+    sources: &[Source],
+) -> Result<()> {
+    let key = format!("source{}", suffix);
+    let entries = pkgbuild.raw_elements(&key)?;
+    if entries.len() != sources.len() {
+        warn!(
+            "cannot add signatures to {}, it is not a single literal array",
+            key
+        );
+        return Ok(());
+    }
+    // Sums arrays get a `SKIP` quoted like their first element.
+    let mut hashsums = Vec::new();
+    for algo in KNOWN_HASH_ALGOS {
+        let hashsum = format!("{}sums{}", algo, suffix);
+        let sums = pkgbuild.raw_elements(&hashsum)?;
+        if sums.len() == sources.len() {
+            let skip = match sums.first().and_then(|sum| sum.chars().next()) {
+                Some(quote @ '"') | Some(quote @ '\'') => format!("{}SKIP{}", quote, quote),
+                _ => "SKIP".to_string(),
+            };
+            hashsums.push((hashsum, skip));
+        }
+    }
+    for (i, source) in sources.iter().enumerate().rev() {
+        if source.is_vcs() || source.is_local() || source.is_signature() {
+            continue;
+        }
+        let signed = sources.iter().any(|other| {
+            SIGNATURE_EXTENSIONS
+                .iter()
+                .any(|extension| other.filename == format!("{}{}", source.filename, extension))
+        });
+        if signed {
+            continue;
+        }
+        let extension = [".sig", ".asc"]
+            .iter()
+            .find(|extension| downloader.exists(&format!("{}{}", source.url, extension)));
+        let extension = match extension {
+            Some(extension) => extension,
+            None => continue,
+        };
+        info!("{}{} (signature, added)", source.url, extension);
+        let entry = &entries[i];
+        let signature = match entry.chars().last() {
+            Some(quote @ '"') | Some(quote @ '\'') => {
+                format!("{}{}{}", &entry[..entry.len() - 1], extension, quote)
+            }
+            _ => format!("{}{}", entry, extension),
+        };
+        pkgbuild.insert_element(&key, i, &signature)?;
+        for (hashsum, skip) in &hashsums {
+            pkgbuild.insert_element(hashsum, i, skip)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fails if `--arch` names an architecture the PKGBUILD does not list in `arch`.
+fn check_arches(opt: &Opt, pkgbuild: &Pkgbuild) -> Result<()> {
+    let arches = pkgbuild.elements("arch")?;
+    match opt.arches.iter().find(|arch| !arches.contains(arch)) {
+        Some(arch) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not in arch=({})", arch, arches.join(" ")),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn extract_metadata(opt: &Opt, pkgbuild: &Pkgbuild) -> Result<Metadata> {
+    if opt.bash {
+        return ExtractPkgbuild::new()?.run(pkgbuild);
+    }
+    // Only the sources and sums can be extracted with bash, so the hint is not given elsewhere.
+    Metadata::parse(pkgbuild).map_err(|e| match e.kind() {
+        ErrorKind::Unsupported => Error::new(e.kind(), format!("{}, try --bash", e)),
+        _ => e,
+    })
+}
+
+/// Rebuilds the current version with the next integer pkgrel, e.g. after a soname bump of a
+/// dependency.
+fn rebuild(opt: &Opt) -> Result<()> {
+    let config = Config::load()?;
+    let mut pkgbuild = Pkgbuild::new()?;
+    let pkgrel = pkgbuild
+        .get("pkgrel")?
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "PKGBUILD has no pkgrel"))?;
+    let release = pkgrel
+        .split('.')
+        .next()
+        .and_then(|release| release.parse::<u32>().ok())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("current pkgrel `{}` is not a number", pkgrel),
+            )
+        })?;
+    let pkgrel = (release + 1).to_string();
+    pkgbuild.set("pkgrel", &pkgrel)?;
+    let pkgver = pkgbuild.get("pkgver")?.unwrap_or_default();
+    info!(
+        "Rebuilding {}-{}",
+        version::full_version(pkgbuild.get("epoch")?.as_deref(), &pkgver),
+        pkgrel
+    );
+    validate::validate(&pkgbuild.content, &[("pkgrel".to_string(), vec![pkgrel])])?;
+    output(&pkgbuild, opt, &config.write)
+}
+
+/// Downloads the current sources and fails if any does not match its sums.
+fn verify(opt: &Opt) -> Result<()> {
+    let config = Config::load()?;
+    let pkgbuild = Pkgbuild::new()?;
+    check_arches(opt, &pkgbuild)?;
+    let metadata = extract_metadata(opt, &pkgbuild)?;
+    let downloader = downloader(opt, &config)?;
+    let signing_keys: Vec<String> = config
+        .package(&metadata.pkgbase)
+        .signing_keys
+        .into_iter()
+        .chain(opt.signing_keys.iter().cloned())
+        .collect();
+    let arch_sources = metadata
+        .arch_sources
+        .iter()
+        .filter(|(arch, _)| opt.arches.is_empty() || opt.arches.contains(arch))
+        .map(|(arch, sources)| (format!("_{}", arch), sources));
+    let mut mismatches = 0;
+    let mut failures = Vec::new();
+    for (suffix, sources) in std::iter::once((String::new(), &metadata.sources)).chain(arch_sources)
+    {
+        let sums = old_sums(&pkgbuild, &metadata.hashes, &suffix)?;
+        let (digest_hashes, _, failed) = downloader
+            .download(
+                sources,
+                &vec![true; sources.len()],
+                &metadata.hashes,
+                &sums,
+                &[],
+                true,
+                opt.jobs,
+            )
+            .map_err(exit::download)?;
+        for ((hash_name, expected), actual) in metadata.hashes.iter().zip(&sums).zip(&digest_hashes)
+        {
+            for (i, source) in sources.iter().enumerate() {
+                let expected = expected.get(i).map_or("", String::as_str);
+                if expected == "SKIP"
+                    || source.is_vcs()
+                    || source.is_signature()
+                    || failed.iter().any(|(failed, _)| *failed == i)
+                {
+                    continue;
+                }
+                if expected.is_empty() {
+                    error!(
+                        "{}: {}sums{} has no sum for it",
+                        source.filename, hash_name, suffix
+                    );
+                    mismatches += 1;
+                } else if expected != actual[i] {
+                    error!(
+                        "{}: {}sums{} has `{}` but the file has `{}`",
+                        source.filename, hash_name, suffix, expected, actual[i]
+                    );
+                    mismatches += 1;
+                }
+            }
+        }
+        failures.extend(failed.into_iter().map(|(i, e)| (sources[i].url.clone(), e)));
+        verify_signatures(&downloader, &pkgbuild, sources, &signing_keys)
+            .map_err(exit::verification)?;
+        if opt.check_archives {
+            check_archives(&downloader, sources).map_err(exit::verification)?;
+        }
+    }
+    report_failures(&failures)?;
+    if mismatches > 0 {
+        return Err(exit::verification(Error::new(
+            ErrorKind::InvalidData,
+            format!("found {} mismatched sums", mismatches),
+        )));
+    }
+    info!("All sources match their sums");
+    Ok(())
+}
+
+/// Checks the sums of `sources` against the checksum files upstream publishes at the URLs of
+/// `checksums`, where `auto` looks for ones next to each source, and fails if any disagrees.
+fn cross_check(
+    downloader: &Downloader,
+    checksums: &[String],
+    version: &str,
+    sources: &[Source],
+    hash_names: &[String],
+    digest_hashes: &[Vec<String>],
+) -> Result<()> {
+    if checksums.is_empty() {
+        return Ok(());
+    }
+    let remote = || {
+        sources
+            .iter()
+            .enumerate()
+            .filter(|(_, source)| ["http", "https", "ftp"].contains(&protocol(&source.url)))
+    };
+    let mut candidates = Vec::new();
+    for checksums in checksums {
+        if checksums == "auto" {
+            for (_, source) in remote() {
+                let file = checksums::file_name(&source.url);
+                for extension in [".sha256", ".sha512"] {
+                    candidates.push((format!("{}{}", source.url, extension), Some(file)));
+                }
+                if let Some((dir, _)) = source.url.rsplit_once('/') {
+                    for name in ["SHA256SUMS", "SHA512SUMS"] {
+                        candidates.push((format!("{}/{}", dir, name), None));
+                    }
+                }
+            }
+        } else {
+            candidates.push((
+                VariableRule::Template(checksums.clone()).render(version)?,
+                None,
+            ));
+        }
+    }
+    let mut fetched = HashSet::new();
+    let mut published = Vec::new();
+    for (url, file) in candidates {
+        if !fetched.insert(url.clone()) {
+            continue;
+        }
+        let content = match downloader.fetch_text(&url)? {
+            Some(content) => content,
+            None => continue,
+        };
+        info!("Checking the sums against {}", url);
+        let algorithm = checksums::algorithm_of(checksums::file_name(&url));
+        for mut entry in checksums::parse(&content) {
+            if entry.file.is_none() {
+                entry.file = file.map(str::to_string);
+            }
+            if entry.algorithm.is_none() {
+                entry.algorithm = algorithm.map(str::to_string);
+            }
+            published.push(entry);
+        }
+    }
+    if published.is_empty() {
+        warn!("found no upstream checksums to check the sums against");
+        return Ok(());
+    }
+
+    let mut mismatches = 0;
+    for (i, source) in remote() {
+        let file = checksums::file_name(&source.url);
+        for (hash_name, sums) in hash_names.iter().zip(digest_hashes) {
+            let sum = &sums[i];
+            if hash_name == "ck" || !sum.chars().all(|c| c.is_ascii_hexdigit()) {
+                continue;
+            }
+            for entry in published
+                .iter()
+                .filter(|entry| entry.file.as_deref() == Some(file) && entry.is_of(hash_name, sum))
+            {
+                if entry.sum == *sum {
+                    info!("{}: {} matches upstream", source.filename, hash_name);
+                } else {
+                    error!(
+                        "{}: upstream publishes {} `{}` but the download has `{}`",
+                        source.filename, hash_name, entry.sum, sum
+                    );
+                    mismatches += 1;
+                }
             }
         }
+    }
+    if mismatches > 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} sums differ from upstream's checksum files", mismatches),
+        ));
+    }
+    Ok(())
+}
 
-        for (digest, hashes) in digests.iter_mut().zip(digest_hashes.iter_mut()) {
-            hashes.push(hex::encode(digest.result_reset()));
+/// Lists the sources that failed to download with the reason, and fails if there are any.
+fn report_failures(failures: &[(String, Error)]) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    for (url, e) in failures {
+        let reason = e.to_string();
+        if reason.contains(url.as_str()) {
+            error!("{}", reason);
+        } else {
+            error!("{}: {}", url, reason);
         }
     }
-    for (hash_name, hashes) in metadata.hashes.iter().zip(digest_hashes) {
-        let hashsum = format!("{}sums", hash_name);
-        pkgbuild.set(
-            &hashsum,
-            &format!(
-                "('{}')",
-                hashes.join(&format!("'\n{}  '", " ".repeat(hashsum.len())))
-            ),
+    Err(exit::download(Error::other(format!(
+        "{} of the sources failed to download",
+        failures.len()
+    ))))
+}
+
+/// The downloader for the `[download]` configuration with the command line's overrides.
+fn downloader(opt: &Opt, config: &Config) -> Result<Downloader> {
+    let mut download_config = config.download.clone();
+    if let Some(retries) = opt.retries {
+        download_config.retries = retries;
+    }
+    if let Some(timeout) = opt.connect_timeout {
+        download_config.connect_timeout = timeout;
+    }
+    if let Some(timeout) = opt.read_timeout {
+        download_config.read_timeout = timeout;
+    }
+    if let Some(redirects) = opt.max_redirects {
+        download_config.max_redirects = redirects;
+    }
+    if let Some(agent) = &opt.user_agent {
+        download_config.user_agent = Some(agent.clone());
+    }
+    if opt.require_https {
+        download_config.require_https = true;
+    }
+    if opt.ipv4 {
+        download_config.ip_version = Some(4);
+    } else if opt.ipv6 {
+        download_config.ip_version = Some(6);
+    }
+    if let Some(proxy) = &opt.proxy {
+        download_config.proxy = Some(proxy.clone());
+    }
+    if let Some(downloader) = &opt.downloader {
+        download_config.downloader = Some(downloader.clone());
+    }
+    if opt.dlagents {
+        download_config.dlagents = true;
+    }
+    if opt.no_cache {
+        download_config.cache = false;
+    }
+    if let Some(rate) = &opt.limit_rate {
+        download_config.limit_rate = Some(rate.clone());
+    }
+    if let Some(rate) = &opt.limit_rate_per_source {
+        download_config.limit_rate_per_source = Some(rate.clone());
+    }
+    if let Some(segments) = opt.segments {
+        download_config.segments = segments;
+    }
+    if opt.no_preflight {
+        download_config.preflight = false;
+    }
+    if let Some(threads) = opt.hash_threads {
+        download_config.hash_threads = Some(threads);
+    }
+    if let Some(path) = &opt.cookie_jar {
+        download_config.cookie_jar = Some(path.clone());
+    }
+    if let Some(size) = &opt.max_size {
+        download_config.max_size = Some(size.clone());
+    }
+    if let Some(size) = &opt.buffer_size {
+        download_config.buffer_size = Some(size.clone());
+    }
+    if opt.no_keep {
+        download_config.keep_sources = false;
+    } else if opt.keep_sources {
+        download_config.keep_sources = true;
+    }
+    let mut downloader = Downloader::new(&download_config, &MakepkgConf::load()?)?;
+    if opt.update_sums {
+        // Refreshed sums should be those of what upstream serves now.
+        downloader.redownload();
+    }
+    if opt.keep_going {
+        downloader.keep_going();
+    }
+    if opt.offline {
+        downloader.offline();
+    }
+    if opt.dry_run {
+        downloader.dry_run();
+    }
+    Ok(downloader)
+}
+
+/// Returns the code to exit with, which tells whether the PKGBUILD changed.
+fn run(opt: Opt) -> Result<i32> {
+    match opt.command {
+        Some(Subcommand::Rebuild) => return rebuild(&opt).map(|()| exit::UPDATED),
+        Some(Subcommand::Verify) => return verify(&opt).map(|()| exit::UPDATED),
+        None => {}
+    }
+    let started = Instant::now();
+    let report_out = if opt.output == "json" {
+        Some(report::redirect_stdout()?)
+    } else {
+        None
+    };
+    let config = Config::load()?;
+    let downloader = downloader(&opt, &config)?;
+    let mut pkgbuild = Pkgbuild::new()?;
+    check_arches(&opt, &pkgbuild)?;
+    let original = pkgbuild.content.clone();
+    let old_metadata = extract_metadata(&opt, &pkgbuild)?;
+    let pkgbase = match pkgbuild.get("pkgbase")? {
+        Some(pkgbase) => pkgbase,
+        None => pkgbuild.get("pkgname")?.unwrap_or_default(),
+    };
+    let package = config.package(&pkgbase);
+    let (upstream_version, new_version) = if opt.from_pkgver {
+        let metadata = extract_metadata(&opt, &pkgbuild)?;
+        let arch_sources = metadata
+            .arch_sources
+            .iter()
+            .filter(|(arch, _)| opt.arches.is_empty() || opt.arches.contains(arch))
+            .flat_map(|(_, sources)| sources);
+        let new_version = run_pkgver(&downloader, metadata.sources.iter().chain(arch_sources))?;
+        info!("pkgver() -> {}", new_version);
+        (new_version.clone(), new_version)
+    } else if opt.update_sums {
+        let version = pkgbuild
+            .get("pkgver")?
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "PKGBUILD has no pkgver"))?;
+        (version.clone(), version)
+    } else {
+        let upstream_version = opt.new_version.clone().unwrap();
+        let new_version = version::normalize(&package.transform_version(&upstream_version)?);
+        if new_version != upstream_version {
+            info!("{} -> {}", upstream_version, new_version);
+        }
+        (upstream_version, new_version)
+    };
+    version::check_pkgver(&new_version)?;
+    let old_epoch = pkgbuild.get("epoch")?;
+    let old_version = pkgbuild.get("pkgver")?;
+    let old_pkgrel = pkgbuild.get("pkgrel")?;
+    let new_epoch = if opt.bump_epoch {
+        let epoch = old_epoch
+            .as_deref()
+            .unwrap_or("0")
+            .parse::<u32>()
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "current epoch is not a number"))?;
+        Some((epoch + 1).to_string())
+    } else {
+        opt.epoch.map(|epoch| epoch.to_string())
+    };
+    let new_full_version =
+        version::full_version(new_epoch.as_deref().or(old_epoch.as_deref()), &new_version);
+    let changed = match &old_version {
+        Some(old_version) => {
+            let old_full_version = version::full_version(old_epoch.as_deref(), old_version);
+            let ordering = version::vercmp(&new_full_version, &old_full_version);
+            if ordering == Ordering::Less {
+                if !opt.allow_downgrade {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            concat!(
+                                "{} is older than {}, use --bump-epoch if upstream changed its ",
+                                "versioning or --allow-downgrade"
+                            ),
+                            new_full_version, old_full_version
+                        ),
+                    ));
+                }
+                warn!(
+                    "downgrading from {} to {}",
+                    old_full_version, new_full_version
+                );
+            }
+            ordering != Ordering::Equal
+        }
+        None => true,
+    };
+    if !changed && !opt.force && !opt.update_sums && opt.pkgrel.is_none() {
+        info!(
+            "{} is already at {}, use --force to update its sums anyway",
+            pkgbase, new_full_version
         );
+        if let Some(out) = report_out {
+            let version = release(&new_full_version, old_pkgrel.as_deref());
+            report::Report {
+                pkgbase,
+                pkgnames: old_metadata.pkgnames.clone(),
+                old_version: Some(version.clone()),
+                new_version: version,
+                sources: Vec::new(),
+                download_seconds: 0.0,
+                seconds: started.elapsed().as_secs_f64(),
+            }
+            .write(out)?;
+        }
+        return Ok(exit::UNCHANGED);
+    }
+    pkgbuild.set("pkgver", &new_version)?;
+    if let Some(old_version) = old_version.as_ref().filter(|old| *old != &new_version) {
+        pkgbuild.rewrite_elements(
+            |name| name == "source" || name.starts_with("source_"),
+            |entry| {
+                let source = Source::new(entry.trim_matches(['"', '\'']));
+                if source.is_vcs() {
+                    return vcs::bump_fragment(entry, old_version, &new_version);
+                }
+                if source.is_local() {
+                    return None;
+                }
+                let bumped = version::replace_version(entry, old_version, &new_version)?;
+                warn!(
+                    "{} hardcodes the version, consider using $pkgver instead",
+                    source
+                );
+                Some(bumped)
+            },
+        )?;
+        bump_dependencies(&mut pkgbuild, old_version, &new_version)?;
+    }
+    for (name, rule) in &package.variables {
+        let value = rule.render(&new_version)?;
+        if !pkgbuild.set(name, &value)? {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("PKGBUILD has no top-level `{}` assignment", name),
+            ));
+        }
+    }
+    let mut expected = vec![("pkgver".to_string(), vec![new_version.clone()])];
+    let mut failures = Vec::new();
+    if let Some(epoch) = &new_epoch {
+        if epoch != "0" {
+            expected.push(("epoch".to_string(), vec![epoch.clone()]));
+        }
+        if !pkgbuild.set("epoch", epoch)?
+            && epoch != "0"
+            && !pkgbuild.insert_after("pkgrel", "epoch", epoch)?
+        {
+            pkgbuild.insert_after("pkgver", "epoch", epoch)?;
+        }
+    }
+
+    let pkgrel = match &opt.pkgrel {
+        Some(pkgrel) => Some(pkgrel.as_str()),
+        None if !opt.keep_pkgrel && changed => Some("1"),
+        None => None,
+    };
+    if let Some(pkgrel) = pkgrel {
+        if pkgbuild.set("pkgrel", pkgrel)? {
+            expected.push(("pkgrel".to_string(), vec![pkgrel.to_string()]));
+        }
+    }
+    if opt.resolve_commit {
+        let repository = pkgbuild
+            .elements("source")?
+            .iter()
+            .map(|entry| Source::new(entry))
+            .find_map(|source| vcs::pinned_repository(&source.url).map(str::to_string))
+            .ok_or_else(|| {
+                Error::new(ErrorKind::NotFound, "no git source is pinned with #commit=")
+            })?;
+        let tags = match &opt.tag {
+            Some(tag) => vec![tag.clone()],
+            None => {
+                let mut tags = vec![
+                    upstream_version.clone(),
+                    format!("v{}", new_version),
+                    new_version.clone(),
+                ];
+                tags.dedup();
+                tags
+            }
+        };
+        downloader.check_host(&repository)?;
+        let (tag, commit) = vcs::resolve_tag(&repository, &tags)?;
+        info!("{} {} -> {}", repository, tag, commit);
+        if !pkgbuild.set("_commit", &commit)? {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "PKGBUILD has no top-level `_commit` assignment",
+            ));
+        }
+    }
+    for (key, value) in &opt.sets {
+        if !pkgbuild.set(key, value)? {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("PKGBUILD has no top-level `{}` assignment", key),
+            ));
+        }
+    }
+    if !opt.add_pgp_keys.is_empty() || !opt.remove_pgp_keys.is_empty() {
+        let keys = update_pgp_keys(
+            &mut pkgbuild,
+            &downloader,
+            &opt.add_pgp_keys,
+            &opt.remove_pgp_keys,
+        )?;
+        expected.push(("validpgpkeys".to_string(), keys));
+    }
+    for (hashsum, reason) in stale_sums(&pkgbuild, opt.prune_sums)? {
+        if opt.prune_sums {
+            info!("Removing {} ({})", hashsum, reason);
+            pkgbuild.remove(&hashsum)?;
+            expected.push((hashsum, Vec::new()));
+        } else {
+            warn!("{} {}, use --prune-sums to remove it", hashsum, reason);
+        }
+    }
+    if opt.add_signatures {
+        let metadata = extract_metadata(&opt, &pkgbuild)?;
+        let arch_sources = metadata
+            .arch_sources
+            .iter()
+            .map(|(arch, sources)| (format!("_{}", arch), sources));
+        for (suffix, sources) in
+            std::iter::once((String::new(), &metadata.sources)).chain(arch_sources)
+        {
+            add_signatures(&mut pkgbuild, &downloader, &suffix, sources)?;
+        }
+    }
+    let mut metadata = extract_metadata(&opt, &pkgbuild)?;
+    let name =
+        if metadata.pkgnames.len() > 1 || metadata.pkgnames.first() != Some(&metadata.pkgbase) {
+            format!("{} ({})", metadata.pkgbase, metadata.pkgnames.join(", "))
+        } else {
+            metadata.pkgbase.clone()
+        };
+    if opt.update_sums {
+        info!("Updating the sums of {} {}", name, new_full_version);
+    } else {
+        info!("Updating {} to {}", name, new_full_version);
+    }
+    let layout = match (opt.sums_style.as_deref(), opt.sums_indent) {
+        (Some("inline"), Some(_)) => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "--sums-indent cannot be used with --sums-style inline",
+            ))
+        }
+        (Some("inline"), None) => Some(Layout::Inline),
+        (Some(_), indent) | (None, indent @ Some(_)) => Some(Layout::PerLine { indent }),
+        (None, None) => None,
+    };
+    let old_hashes = metadata.hashes.clone();
+    if let Some(algo) = &opt.migrate_sums {
+        metadata.hashes = vec![algo.clone()];
+    }
+    let rewrites = package.url_rewrites(&new_version)?;
+    let checksums: Vec<String> = package
+        .checksums
+        .iter()
+        .chain(&opt.checksums)
+        .cloned()
+        .collect();
+    let signing_keys: Vec<String> = package
+        .signing_keys
+        .iter()
+        .chain(&opt.signing_keys)
+        .cloned()
+        .collect();
+    let arch_sources = metadata
+        .arch_sources
+        .iter()
+        .filter(|(arch, _)| opt.arches.is_empty() || opt.arches.contains(arch))
+        .map(|(arch, sources)| (format!("_{}", arch), sources));
+    let mut source_reports = Vec::new();
+    let mut download_time = Duration::ZERO;
+    for (suffix, sources) in std::iter::once((String::new(), &metadata.sources)).chain(arch_sources)
+    {
+        let sums = match &opt.migrate_sums {
+            Some(_) => vec![skipped_sums(&old_sums(&pkgbuild, &old_hashes, &suffix)?)],
+            None => old_sums(&pkgbuild, &metadata.hashes, &suffix)?,
+        };
+        let (digest_hashes, moved, failed) = if opt.skip_sums {
+            let skip = vec!["SKIP".to_string(); sources.len()];
+            (vec![skip; metadata.hashes.len()], Vec::new(), Vec::new())
+        } else {
+            let old_sources = match suffix.strip_prefix('_') {
+                Some(arch) => old_metadata.arch_sources.get(arch),
+                None => Some(&old_metadata.sources),
+            };
+            let unchanged: Vec<_> = sources
+                .iter()
+                .enumerate()
+                .map(|(i, source)| {
+                    old_sources
+                        .and_then(|old_sources| old_sources.get(i))
+                        .is_some_and(|old| old.to_string() == source.to_string())
+                })
+                .collect();
+            let download_started = Instant::now();
+            let downloaded = downloader
+                .download(
+                    sources,
+                    &unchanged,
+                    &metadata.hashes,
+                    &sums,
+                    &rewrites,
+                    !opt.no_download,
+                    opt.jobs,
+                )
+                .map_err(exit::download)?;
+            download_time += download_started.elapsed();
+            if opt.diff_sources && !opt.no_download && !opt.dry_run {
+                diff_sources(
+                    &downloader,
+                    &metadata.hashes,
+                    &sums,
+                    old_sources.map_or(&[], Vec::as_slice),
+                    sources,
+                    opt.jobs,
+                );
+            }
+            downloaded
+        };
+        failures.extend(failed.into_iter().map(|(i, e)| (sources[i].url.clone(), e)));
+        if !opt.dry_run {
+            cross_check(
+                &downloader,
+                &checksums,
+                &new_version,
+                sources,
+                &metadata.hashes,
+                &digest_hashes,
+            )
+            .map_err(exit::verification)?;
+        }
+        if report_out.is_some() {
+            let before = old_sums(&pkgbuild, &old_hashes, &suffix)?;
+            let sums_of = |hash_names: &[String], sums: &[Vec<String>], i: usize| {
+                hash_names
+                    .iter()
+                    .zip(sums)
+                    .filter_map(|(name, sums)| Some((name.clone(), sums.get(i)?.clone())))
+                    .collect()
+            };
+            source_reports.extend(sources.iter().enumerate().map(|(i, source)| {
+                report::SourceReport {
+                    arch: suffix.strip_prefix('_').map(str::to_string),
+                    url: source.url.clone(),
+                    filename: source.filename.clone(),
+                    size: downloader
+                        .path(source)
+                        .and_then(|path| path.metadata().ok())
+                        .filter(|metadata| metadata.is_file())
+                        .map(|metadata| metadata.len()),
+                    old_sums: sums_of(&old_hashes, &before, i),
+                    new_sums: sums_of(&metadata.hashes, &digest_hashes, i),
+                }
+            }));
+        }
+        if !moved.is_empty() {
+            relocate_sources(&mut pkgbuild, &format!("source{}", suffix), sources, &moved)?;
+        }
+        expected.extend(set_sums(
+            &mut pkgbuild,
+            &metadata.hashes,
+            &suffix,
+            &sums,
+            digest_hashes,
+            layout,
+        )?);
+        for old in old_hashes
+            .iter()
+            .filter(|old| !metadata.hashes.contains(old))
+        {
+            let hashsum = format!("{}sums{}", old, suffix);
+            pkgbuild.remove(&hashsum)?;
+            expected.push((hashsum, Vec::new()));
+        }
+    }
+    if opt.offline {
+        report_failures(&failures)?;
+    }
+    if !opt.no_download && !opt.skip_sums && !opt.dry_run {
+        let arch_sources = metadata
+            .arch_sources
+            .iter()
+            .filter(|(arch, _)| opt.arches.is_empty() || opt.arches.contains(arch))
+            .map(|(_, sources)| sources);
+        for sources in std::iter::once(&metadata.sources).chain(arch_sources) {
+            verify_signatures(&downloader, &pkgbuild, sources, &signing_keys)
+                .map_err(exit::verification)?;
+            if opt.check_archives {
+                check_archives(&downloader, sources).map_err(exit::verification)?;
+            }
+        }
+    }
+    expected.retain(|(key, _)| !opt.sets.iter().any(|(set, _)| set == key));
+    validate::validate(&pkgbuild.content, &expected)?;
+    output(&pkgbuild, &opt, &config.write)?;
+    if opt.changelog {
+        append_changelog(&pkgbuild, &opt.changelog_message)?;
+    }
+    report_failures(&failures)?;
+    if let Some(out) = report_out {
+        report::Report {
+            pkgbase: metadata.pkgbase.clone(),
+            pkgnames: metadata.pkgnames.clone(),
+            old_version: old_version.map(|old_version| {
+                release(
+                    &version::full_version(old_epoch.as_deref(), &old_version),
+                    old_pkgrel.as_deref(),
+                )
+            }),
+            new_version: release(&new_full_version, pkgbuild.get("pkgrel")?.as_deref()),
+            sources: source_reports,
+            download_seconds: download_time.as_secs_f64(),
+            seconds: started.elapsed().as_secs_f64(),
+        }
+        .write(out)?;
     }
-    println!("{}", pkgbuild.content);
 
     // TODO:
-    // - Generate .SRCINFO
     // - Create git commit
-    // - Write altered PKGBUILD
     // - Run namcap?
     // - Build package?
+    if pkgbuild.content == original {
+        Ok(exit::UNCHANGED)
+    } else {
+        Ok(exit::UPDATED)
+    }
+}
+
+/// `full_version` with `pkgrel`, as pacman shows the versions of packages.
+fn release(full_version: &str, pkgrel: Option<&str>) -> String {
+    format!("{}-{}", full_version, pkgrel.unwrap_or("1"))
+}
+
+/// Writes the updated PKGBUILD in place, keeping the one it replaces as configured, or prints it.
+fn output(pkgbuild: &Pkgbuild, opt: &Opt, config: &WriteConfig) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    if opt.diff || opt.dry_run {
+        let color = match opt.color.as_str() {
+            "always" => true,
+            "never" => false,
+            _ => stdout.is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        };
+        let old = Pkgbuild::new()?;
+        stdout.write_all(&diff::unified(
+            &old.content,
+            &pkgbuild.content,
+            "PKGBUILD",
+            color,
+        ))?;
+    } else if !opt.write {
+        stdout.write_all(&pkgbuild.content)?;
+        writeln!(stdout)?;
+    }
+    if !opt.write {
+        return Ok(());
+    }
+    let backup = if config.backup && !opt.no_backup {
+        Some(backup_path(config)?)
+    } else {
+        None
+    };
+    pkgbuild.write(backup.as_deref())?;
+    match backup {
+        Some(backup) => info!("Updated PKGBUILD, kept the old one as {}", backup.display()),
+        None => info!("Updated PKGBUILD"),
+    }
+    if config.srcinfo && !opt.no_srcinfo {
+        srcinfo::update(&pkgbuild.content)?;
+        info!("Updated .SRCINFO");
+    }
     Ok(())
 }
 
+/// Where to keep the PKGBUILD on disk when it is replaced. In a backup directory, it is named
+/// after its version, e.g. `PKGBUILD.1.2.0-1`, so every bump can be reverted.
+fn backup_path(config: &WriteConfig) -> Result<PathBuf> {
+    let dir = match &config.backup_dir {
+        Some(dir) => Path::new(dir),
+        None => return Ok(PathBuf::from(&config.backup_file)),
+    };
+    let original = Pkgbuild::new()?;
+    let pkgver = original.get("pkgver")?.unwrap_or_default();
+    let version = version::full_version(original.get("epoch")?.as_deref(), &pkgver);
+    let pkgrel = original.get("pkgrel")?.unwrap_or_else(|| "1".to_string());
+    std::fs::create_dir_all(dir)?;
+    Ok(dir.join(format!("PKGBUILD.{}-{}", version, pkgrel)))
+}
+
 fn main() {
-    if let Err(e) = run(Opt::from_args()) {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+    let opt = Opt::from_args();
+    logging::init(match (opt.quiet, opt.verbose) {
+        (true, _) => Level::WARN,
+        (false, 0) => Level::INFO,
+        (false, 1) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    });
+    match run(opt) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(exit::code(&e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sums(pkgbuild: &[u8], hashes: &[&str]) -> Result<Pkgbuild> {
+        let mut pkgbuild = Pkgbuild {
+            content: pkgbuild.to_vec(),
+        };
+        set_sums(
+            &mut pkgbuild,
+            &["sha256".to_string()],
+            "",
+            &[Vec::new()],
+            vec![hashes.iter().map(|hash| hash.to_string()).collect()],
+            None,
+        )?;
+        Ok(pkgbuild)
+    }
+
+    #[test]
+    fn sums_refuse_sources_of_branches() {
+        let err = sums(
+            b"source=(foo.tar.gz)\n\
+              if [[ $CARCH = x86_64 ]]; then\n  source+=(a.patch)\nelse\n  source+=(b.patch)\nfi\n\
+              sha256sums=('SKIP')\n",
+            &["1", "2", "3"],
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn sums_follow_sources_of_branches() {
+        let pkgbuild = sums(
+            b"source=(foo.tar.gz)\nsha256sums=('SKIP')\n\
+              if [[ $CARCH = x86_64 ]]; then\n  source+=(a.patch)\n  sha256sums+=('SKIP')\n\
+              else\n  source+=(b.patch)\n  sha256sums+=('SKIP')\nfi\n",
+            &["1", "2", "3"],
+        )
+        .unwrap();
+        assert_eq!(
+            pkgbuild.elements("sha256sums").unwrap(),
+            vec!["1", "2", "3"]
+        );
     }
 }
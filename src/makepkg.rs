@@ -0,0 +1,90 @@
+//! Settings from makepkg.conf, read from the same files makepkg reads: `$MAKEPKG_CONF` or
+//! `/etc/makepkg.conf`, the `.conf` files in `/etc/makepkg.conf.d`, and then the user's
+//! `$XDG_CONFIG_HOME/pacman/makepkg.conf` or else `~/.makepkg.conf`.
+
+use crate::parser::{self, Parsed};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+pub struct MakepkgConf {
+    /// In the order makepkg sources them, so later files override earlier ones.
+    files: Vec<(PathBuf, Parsed)>,
+}
+
+impl MakepkgConf {
+    pub fn load() -> Result<MakepkgConf> {
+        let mut paths = vec![std::env::var_os("MAKEPKG_CONF")
+            .map_or_else(|| PathBuf::from("/etc/makepkg.conf"), PathBuf::from)];
+        match std::fs::read_dir("/etc/makepkg.conf.d") {
+            Ok(entries) => {
+                let mut dropins = Vec::new();
+                for entry in entries {
+                    let path = entry?.path();
+                    if path
+                        .extension()
+                        .is_some_and(|extension| extension == "conf")
+                    {
+                        dropins.push(path);
+                    }
+                }
+                dropins.sort();
+                paths.extend(dropins);
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let user = crate::config::config_dir()
+            .map(|config_home| config_home.join("pacman/makepkg.conf"))
+            .filter(|path| path.exists())
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| Path::new(&home).join(".makepkg.conf"))
+            });
+        paths.extend(user);
+
+        let mut files = Vec::new();
+        for path in paths {
+            let content = match std::fs::read(&path) {
+                Ok(content) => content,
+                Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(context(&path, e)),
+            };
+            let parsed = parser::parse(&content).map_err(|e| context(&path, e))?;
+            files.push((path, parsed));
+        }
+        Ok(MakepkgConf { files })
+    }
+
+    /// The array `name` from the last file that sets it.
+    pub fn array(&self, name: &str) -> Result<Option<Vec<String>>> {
+        for (path, parsed) in self.files.iter().rev() {
+            if let Some(value) = parsed.get(name).map_err(|e| context(path, e))? {
+                return Ok(Some(value.values().to_vec()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The scalar `name` from the last file that sets it.
+    pub fn scalar(&self, name: &str) -> Result<Option<String>> {
+        Ok(self
+            .array(name)?
+            .and_then(|values| values.into_iter().next()))
+    }
+
+    /// The directory makepkg keeps downloaded sources in if not next to the PKGBUILD, from the
+    /// `SRCDEST` environment variable or else the configuration.
+    pub fn srcdest(&self) -> Result<Option<PathBuf>> {
+        let srcdest = match std::env::var_os("SRCDEST") {
+            Some(srcdest) if !srcdest.is_empty() => Some(PathBuf::from(srcdest)),
+            _ => self
+                .scalar("SRCDEST")?
+                .filter(|srcdest| !srcdest.is_empty())
+                .map(PathBuf::from),
+        };
+        Ok(srcdest)
+    }
+}
+
+fn context(path: &Path, e: Error) -> Error {
+    Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
@@ -0,0 +1,67 @@
+//! Logins from `~/.netrc`, or the file `$NETRC` names, as curl reads them.
+
+use std::io::{ErrorKind, Result};
+use std::path::PathBuf;
+
+/// A `machine` entry, or the `default` one without a machine.
+#[derive(Debug, Clone)]
+pub struct Login {
+    pub machine: Option<String>,
+    pub login: String,
+    pub password: String,
+}
+
+/// Reads the logins, none if there is no netrc file.
+pub fn load() -> Result<Vec<Login>> {
+    let path = match std::env::var_os("NETRC") {
+        Some(path) => PathBuf::from(path),
+        None => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".netrc"),
+            None => return Ok(Vec::new()),
+        },
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(parse(&content)),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn parse(content: &str) -> Vec<Login> {
+    let mut logins = Vec::new();
+    let mut current: Option<Login> = None;
+    let mut tokens = content
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(str::split_whitespace);
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" => {
+                logins.extend(current.take());
+                current = Some(Login {
+                    machine: if token == "machine" {
+                        tokens.next().map(str::to_ascii_lowercase)
+                    } else {
+                        None
+                    },
+                    login: String::new(),
+                    password: String::new(),
+                });
+            }
+            "login" | "password" => {
+                if let (Some(login), Some(value)) = (&mut current, tokens.next()) {
+                    if token == "login" {
+                        login.login = value.to_string();
+                    } else {
+                        login.password = value.to_string();
+                    }
+                }
+            }
+            // Macros run until an empty line, which splitting into tokens cannot see, so stop.
+            "macdef" => break,
+            _ => {}
+        }
+    }
+    logins.extend(current);
+    logins
+}
@@ -0,0 +1,1574 @@
+//! Native PKGBUILD parser.
+//!
+//! Understands the subset of bash used by practically every PKGBUILD: top-level scalar and
+//! array assignments, quoting, brace expansion and parameter expansion. Function bodies are
+//! skipped, except for the assignments of split packages' `package_*` functions, and anything
+//! that would require running code (command substitution) only fails once a variable depending
+//! on it is actually requested.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Error, ErrorKind, Result};
+use std::ops::Range;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+impl Value {
+    pub fn values(&self) -> &[String] {
+        match self {
+            Value::Scalar(value) => std::slice::from_ref(value),
+            Value::Array(values) => values,
+        }
+    }
+
+    fn append(self, other: Value) -> Value {
+        match (self, other) {
+            (Value::Scalar(mut a), Value::Scalar(b)) => {
+                a.push_str(&b);
+                Value::Scalar(a)
+            }
+            (Value::Array(mut a), Value::Scalar(b)) => {
+                match a.first_mut() {
+                    Some(first) => first.push_str(&b),
+                    None => a.push(b),
+                }
+                Value::Array(a)
+            }
+            (Value::Scalar(a), Value::Array(b)) => {
+                Value::Array(std::iter::once(a).chain(b).collect())
+            }
+            (Value::Array(mut a), Value::Array(b)) => {
+                a.extend(b);
+                Value::Array(a)
+            }
+        }
+    }
+}
+
+/// A top-level `name=value` or `name+=value` statement, with the byte ranges of its value and,
+/// for arrays, of each element. Statements inside `if`, `case` and loop bodies or after `&&`
+/// and `||` are `conditional`; they are still evaluated as if they ran, every branch of an `if`
+/// or `case` included, so only arrays built up in the same branches line up.
+#[derive(Debug)]
+pub struct Assignment {
+    pub name: String,
+    pub append: bool,
+    pub conditional: bool,
+    pub value: Range<usize>,
+    pub elements: Vec<Range<usize>>,
+    /// What removing the assignment removes: from its name to the end of its value, or all of a
+    /// `declare`, `local` etc. that declares nothing else.
+    pub statement: Range<usize>,
+    /// What this statement alone assigns, if it could be evaluated.
+    values: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Parsed {
+    assignments: Vec<Assignment>,
+    vars: HashMap<String, Value>,
+    unsupported: HashMap<String, String>,
+    /// The variables each `package_*` function sets, or why they cannot be evaluated.
+    functions: HashMap<String, HashMap<String, std::result::Result<Value, String>>>,
+}
+
+impl Parsed {
+    pub fn assignments(&self) -> &[Assignment] {
+        &self.assignments
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<&Value>> {
+        if let Some(reason) = self.unsupported.get(name) {
+            return Err(unsupported(format!(
+                "cannot evaluate `{}` natively ({})",
+                name, reason
+            )));
+        }
+        Ok(self.vars.get(name))
+    }
+
+    pub fn array(&self, name: &str) -> Result<Vec<String>> {
+        Ok(self
+            .get(name)?
+            .map_or_else(Vec::new, |value| value.values().to_vec()))
+    }
+
+    /// The values `name` is put together from. An array built up over several statements that
+    /// are appends or conditional has one segment per statement, so it lines up with arrays
+    /// built the same way whichever branches run; any other variable is a single segment.
+    pub fn segments(&self, name: &str) -> Result<Vec<Vec<String>>> {
+        let statements: Vec<_> = self
+            .assignments
+            .iter()
+            .filter(|assignment| assignment.name == name)
+            .collect();
+        let spread = statements.len() > 1
+            && statements[1..]
+                .iter()
+                .all(|assignment| assignment.append || assignment.conditional);
+        if !spread {
+            return Ok(vec![self.array(name)?]);
+        }
+        self.get(name)?;
+        statements
+            .iter()
+            .map(|assignment| {
+                assignment
+                    .values
+                    .clone()
+                    .ok_or_else(|| unsupported(format!("cannot evaluate `{}` natively", name)))
+            })
+            .collect()
+    }
+
+    /// Whether some statement assigning `name` only runs in some branches.
+    pub fn conditional(&self, name: &str) -> bool {
+        self.assignments
+            .iter()
+            .any(|assignment| assignment.name == name && assignment.conditional)
+    }
+
+    /// All values of `name` across its segments.
+    pub fn elements(&self, name: &str) -> Result<Vec<String>> {
+        Ok(self.segments(name)?.concat())
+    }
+
+    /// The value of `name` after the package function `function` assigned it, like makepkg sees
+    /// it for that package, or `None` if the function does not assign it.
+    pub fn function_value(&self, function: &str, name: &str) -> Result<Option<&Value>> {
+        match self.functions.get(function).and_then(|vars| vars.get(name)) {
+            None => Ok(None),
+            Some(Ok(value)) => Ok(Some(value)),
+            Some(Err(reason)) => Err(unsupported(format!(
+                "cannot evaluate `{}` of {}() natively ({})",
+                name, function, reason
+            ))),
+        }
+    }
+
+    pub fn scalar(&self, name: &str) -> Result<Option<String>> {
+        Ok(self
+            .get(name)?
+            .and_then(|value| value.values().first().cloned()))
+    }
+
+    fn element(&self, index: &Index, value: Option<&Value>) -> Result<Option<String>> {
+        let value = match value {
+            Some(value) => value.values(),
+            None => return Ok(None),
+        };
+        Ok(match index {
+            Index::Item(i) => {
+                let i = self.expand_text(i)?;
+                let i: i64 = i
+                    .trim()
+                    .parse()
+                    .map_err(|_| unsupported(format!("array index `{}`", i)))?;
+                let i = if i < 0 { value.len() as i64 + i } else { i };
+                if i < 0 {
+                    None
+                } else {
+                    value.get(i as usize).cloned()
+                }
+            }
+            _ => value.first().cloned(),
+        })
+    }
+
+    fn expand_param(&self, param: &Param) -> Result<(Vec<String>, bool)> {
+        let value = self.get(&param.name)?;
+        if let ParamOp::Length = param.op {
+            let len = match (&param.index, value) {
+                (Index::All, value) => value.map_or(0, |value| value.values().len()),
+                (index, value) => self
+                    .element(index, value)?
+                    .map_or(0, |value| value.chars().count()),
+            };
+            return Ok((vec![len.to_string()], false));
+        }
+
+        let multi = param.index == Index::All;
+        let values = if multi {
+            value.map(|value| value.values().to_vec())
+        } else {
+            self.element(&param.index, value)?.map(|value| vec![value])
+        };
+        let is_null = values
+            .as_ref()
+            .is_none_or(|values| values.iter().all(String::is_empty));
+        let values = match &param.op {
+            ParamOp::None | ParamOp::Length => values.unwrap_or_default(),
+            ParamOp::Default { colon, word } => match values {
+                Some(values) if !(*colon && is_null) => values,
+                _ => return Ok((vec![self.expand_text(word)?], false)),
+            },
+            ParamOp::Alternative { colon, word } => match values {
+                Some(_) if !(*colon && is_null) => {
+                    return Ok((vec![self.expand_text(word)?], false))
+                }
+                _ => Vec::new(),
+            },
+            op => values
+                .unwrap_or_default()
+                .iter()
+                .map(|value| self.transform(value, op))
+                .collect::<Result<_>>()?,
+        };
+        Ok((values, multi))
+    }
+
+    fn transform(&self, value: &str, op: &ParamOp) -> Result<String> {
+        let chars: Vec<char> = value.chars().collect();
+        let slice = |range: std::ops::Range<usize>| chars[range].iter().collect::<String>();
+        Ok(match op {
+            ParamOp::Remove {
+                suffix,
+                longest,
+                pattern,
+            } => {
+                let pattern: Vec<char> = self.expand_text(pattern)?.chars().collect();
+                let len = chars.len();
+                let mut cuts: Vec<usize> = (0..=len).collect();
+                if *longest != *suffix {
+                    cuts.reverse();
+                }
+                let cut = cuts.into_iter().find(|&i| {
+                    if *suffix {
+                        glob_match(&pattern, &chars[i..])
+                    } else {
+                        glob_match(&pattern, &chars[..i])
+                    }
+                });
+                match cut {
+                    Some(i) if *suffix => slice(0..i),
+                    Some(i) => slice(i..len),
+                    None => value.to_string(),
+                }
+            }
+            ParamOp::Replace {
+                mode,
+                pattern,
+                replacement,
+            } => {
+                let pattern: Vec<char> = self.expand_text(pattern)?.chars().collect();
+                let replacement = self.expand_text(replacement)?;
+                if pattern.is_empty() {
+                    return Ok(value.to_string());
+                }
+                let mut result = String::new();
+                let mut start = 0;
+                while start <= chars.len() {
+                    let end = (start..=chars.len()).rev().find(|&end| {
+                        (*mode != ReplaceMode::Suffix || end == chars.len())
+                            && glob_match(&pattern, &chars[start..end])
+                    });
+                    match end {
+                        Some(end) => {
+                            result.push_str(&replacement);
+                            if *mode != ReplaceMode::All {
+                                result.extend(&chars[end..]);
+                                return Ok(result);
+                            }
+                            if end == start {
+                                result.extend(chars.get(start));
+                                start += 1;
+                            } else {
+                                start = end;
+                            }
+                        }
+                        None if *mode == ReplaceMode::Prefix => return Ok(value.to_string()),
+                        None => {
+                            result.extend(chars.get(start));
+                            start += 1;
+                        }
+                    }
+                }
+                result
+            }
+            ParamOp::Case { upper, all } => {
+                let convert = |c: &char| -> String {
+                    if *upper {
+                        c.to_uppercase().collect()
+                    } else {
+                        c.to_lowercase().collect()
+                    }
+                };
+                match chars.split_first() {
+                    Some((first, rest)) if !*all => {
+                        convert(first) + &rest.iter().collect::<String>()
+                    }
+                    _ => chars.iter().map(convert).collect(),
+                }
+            }
+            ParamOp::Substring { offset, length } => {
+                let len = chars.len() as i64;
+                let start = if *offset < 0 { len + offset } else { *offset }.clamp(0, len);
+                let end = match length {
+                    None => len,
+                    Some(length) if *length < 0 => len + length,
+                    Some(length) => start + length,
+                }
+                .clamp(start, len);
+                slice(start as usize..end as usize)
+            }
+            ParamOp::None
+            | ParamOp::Length
+            | ParamOp::Default { .. }
+            | ParamOp::Alternative { .. } => value.to_string(),
+        })
+    }
+
+    fn expand_fields(&self, parts: &[Part]) -> Result<Vec<String>> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        for part in parts {
+            match part {
+                Part::Literal { text, quoted: q } => {
+                    fields.last_mut().unwrap().push_str(text);
+                    quoted |= *q;
+                }
+                Part::Param { param, quoted: q } => {
+                    let (values, multi) = self.expand_param(param)?;
+                    if multi {
+                        let mut values = values.into_iter();
+                        if let Some(first) = values.next() {
+                            fields.last_mut().unwrap().push_str(&first);
+                            quoted = true;
+                        }
+                        fields.extend(values);
+                    } else {
+                        fields.last_mut().unwrap().push_str(&values.join(" "));
+                        quoted |= *q;
+                    }
+                }
+                Part::Command(command) => {
+                    return Err(unsupported(format!("command substitution `{}`", command)))
+                }
+            }
+        }
+        if !quoted && fields.len() == 1 && fields[0].is_empty() {
+            fields.clear();
+        }
+        Ok(fields)
+    }
+
+    fn expand_scalar(&self, word: &Word) -> Result<String> {
+        Ok(self.expand_fields(&word.parts)?.join(" "))
+    }
+
+    fn expand_array(&self, words: &[Word]) -> Result<Vec<String>> {
+        let mut values = BTreeMap::new();
+        let mut next = 0;
+        for word in words {
+            if let Some((index, value)) = word.element() {
+                let index = self.expand_text(&index)?;
+                let index: i64 = index
+                    .trim()
+                    .parse()
+                    .map_err(|_| unsupported(format!("array index `{}`", index)))?;
+                values.insert(index, self.expand_scalar(&value)?);
+                next = index + 1;
+                continue;
+            }
+            for parts in brace_expand(&word.parts) {
+                for field in self.expand_fields(&parts)? {
+                    values.insert(next, field);
+                    next += 1;
+                }
+            }
+        }
+        Ok(values.into_values().collect())
+    }
+
+    fn expand_text(&self, text: &str) -> Result<String> {
+        let word = Lexer::new(text.as_bytes()).word(true)?;
+        self.expand_scalar(&word)
+    }
+}
+
+/// Parses PKGBUILD source. It need not be UTF-8, and lines may end with CRLF.
+pub fn parse(src: &[u8]) -> Result<Parsed> {
+    let mut parser = Parser {
+        lexer: Lexer::new(src),
+        peeked: None,
+        parsed: Parsed::default(),
+        depth: 0,
+        and_or: false,
+        function: None,
+        function_assignments: Vec::new(),
+    };
+    parser.program()?;
+    // Package functions run once the whole PKGBUILD is sourced, so they see every global.
+    for (function, name, append, value) in std::mem::take(&mut parser.function_assignments) {
+        let parsed = &parser.parsed;
+        let value = match &value {
+            AssignedValue::Scalar(word) => parsed.expand_scalar(word).map(Value::Scalar),
+            AssignedValue::Array(words) => parsed.expand_array(words).map(Value::Array),
+        };
+        let vars = parsed.functions.get(&function);
+        let old = match vars.and_then(|vars| vars.get(&name)) {
+            Some(old) => old.clone().map(Some),
+            None => parsed
+                .get(&name)
+                .map(|old| old.cloned())
+                .map_err(|e| e.to_string()),
+        };
+        let value = match (value, old) {
+            (Err(e), _) => Err(e.to_string()),
+            (Ok(value), _) if !append => Ok(value),
+            (Ok(value), Ok(Some(old))) => Ok(old.append(value)),
+            (Ok(value), Ok(None)) => Ok(value),
+            (Ok(_), Err(reason)) => Err(reason),
+        };
+        parser
+            .parsed
+            .functions
+            .entry(function)
+            .or_default()
+            .insert(name, value);
+    }
+    Ok(parser.parsed)
+}
+
+fn unsupported(message: String) -> Error {
+    Error::new(ErrorKind::Unsupported, message)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Index {
+    None,
+    All,
+    Item(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ReplaceMode {
+    First,
+    All,
+    Prefix,
+    Suffix,
+}
+
+#[derive(Debug, Clone)]
+enum ParamOp {
+    None,
+    Length,
+    Default {
+        colon: bool,
+        word: String,
+    },
+    Alternative {
+        colon: bool,
+        word: String,
+    },
+    Remove {
+        suffix: bool,
+        longest: bool,
+        pattern: String,
+    },
+    Replace {
+        mode: ReplaceMode,
+        pattern: String,
+        replacement: String,
+    },
+    Case {
+        upper: bool,
+        all: bool,
+    },
+    Substring {
+        offset: i64,
+        length: Option<i64>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Param {
+    name: String,
+    index: Index,
+    op: ParamOp,
+}
+
+impl Param {
+    fn simple(name: String) -> Param {
+        Param {
+            name,
+            index: Index::None,
+            op: ParamOp::None,
+        }
+    }
+
+    fn parse(raw: &str) -> Option<Param> {
+        let (length, rest) = match raw.strip_prefix('#') {
+            Some(rest) if !rest.is_empty() => (true, rest),
+            _ => (false, raw),
+        };
+        let name_len = match rest.chars().next()? {
+            c if c == '_' || c.is_ascii_alphabetic() => rest
+                .find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len()),
+            c if c.is_ascii_digit() => rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len()),
+            '@' | '*' | '#' | '?' | '$' | '!' | '-' => 1,
+            _ => return None,
+        };
+        let (name, mut rest) = rest.split_at(name_len);
+        let mut index = Index::None;
+        if let Some(subscript) = rest.strip_prefix('[') {
+            let end = subscript.find(']')?;
+            index = match &subscript[..end] {
+                "@" | "*" => Index::All,
+                i => Index::Item(i.to_string()),
+            };
+            rest = &subscript[end + 1..];
+        }
+        let op = if length {
+            if !rest.is_empty() {
+                return None;
+            }
+            ParamOp::Length
+        } else {
+            Self::parse_op(rest)?
+        };
+        Some(Param {
+            name: name.to_string(),
+            index,
+            op,
+        })
+    }
+
+    fn parse_op(op: &str) -> Option<ParamOp> {
+        let word = |skip: usize| op[skip..].to_string();
+        Some(match op.as_bytes() {
+            [] => ParamOp::None,
+            [b':', b'-', ..] | [b':', b'=', ..] | [b':', b'?', ..] => ParamOp::Default {
+                colon: true,
+                word: word(2),
+            },
+            [b':', b'+', ..] => ParamOp::Alternative {
+                colon: true,
+                word: word(2),
+            },
+            [b':', ..] => {
+                let mut fields = op[1..].splitn(2, ':').map(|field| {
+                    field
+                        .trim()
+                        .trim_start_matches('(')
+                        .trim_end_matches(')')
+                        .trim()
+                        .parse::<i64>()
+                });
+                ParamOp::Substring {
+                    offset: fields.next()?.ok()?,
+                    length: match fields.next() {
+                        Some(length) => Some(length.ok()?),
+                        None => None,
+                    },
+                }
+            }
+            [b'-', ..] | [b'=', ..] | [b'?', ..] => ParamOp::Default {
+                colon: false,
+                word: word(1),
+            },
+            [b'+', ..] => ParamOp::Alternative {
+                colon: false,
+                word: word(1),
+            },
+            [b'#', b'#', ..] | [b'%', b'%', ..] => ParamOp::Remove {
+                suffix: op.starts_with('%'),
+                longest: true,
+                pattern: word(2),
+            },
+            [b'#', ..] | [b'%', ..] => ParamOp::Remove {
+                suffix: op.starts_with('%'),
+                longest: false,
+                pattern: word(1),
+            },
+            [b'/', rest @ ..] => {
+                let (mode, skip) = match rest.first() {
+                    Some(b'/') => (ReplaceMode::All, 2),
+                    Some(b'#') => (ReplaceMode::Prefix, 2),
+                    Some(b'%') => (ReplaceMode::Suffix, 2),
+                    _ => (ReplaceMode::First, 1),
+                };
+                let rest = &op[skip..];
+                let mut escaped = false;
+                let split = rest.find(|c: char| {
+                    let found = c == '/' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    found
+                });
+                let (pattern, replacement) = match split {
+                    Some(i) => (&rest[..i], &rest[i + 1..]),
+                    None => (rest, ""),
+                };
+                ParamOp::Replace {
+                    mode,
+                    pattern: pattern.to_string(),
+                    replacement: replacement.to_string(),
+                }
+            }
+            [c @ b'^', rest @ ..] | [c @ b',', rest @ ..] => ParamOp::Case {
+                upper: *c == b'^',
+                all: rest.first() == Some(c),
+            },
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal { text: String, quoted: bool },
+    Param { param: Param, quoted: bool },
+    Command(String),
+}
+
+#[derive(Debug, Clone)]
+struct Word {
+    parts: Vec<Part>,
+    span: Range<usize>,
+}
+
+impl Word {
+    fn is_literal(&self, literal: &str) -> bool {
+        match self.parts.as_slice() {
+            [Part::Literal {
+                text,
+                quoted: false,
+            }] => text == literal,
+            _ => false,
+        }
+    }
+
+    /// Splits a `[index]=value` array element.
+    fn element(&self) -> Option<(String, Word)> {
+        let (text, rest) = match self.parts.split_first()? {
+            (
+                Part::Literal {
+                    text,
+                    quoted: false,
+                },
+                rest,
+            ) => (text.strip_prefix('[')?, rest),
+            _ => return None,
+        };
+        let end = text.find("]=")?;
+        let mut value = Vec::new();
+        if end + 2 < text.len() {
+            value.push(Part::Literal {
+                text: text[end + 2..].to_string(),
+                quoted: false,
+            });
+        }
+        value.extend(rest.iter().cloned());
+        let value = Word {
+            parts: value,
+            span: self.span.start + end + 3..self.span.end,
+        };
+        Some((text[..end].to_string(), value))
+    }
+
+    fn assignment(&self) -> Option<(String, bool, Word)> {
+        let (text, rest) = match self.parts.split_first()? {
+            (
+                Part::Literal {
+                    text,
+                    quoted: false,
+                },
+                rest,
+            ) => (text, rest),
+            _ => return None,
+        };
+        let eq = text.find('=')?;
+        let (name, append) = match text[..eq].strip_suffix('+') {
+            Some(name) => (name, true),
+            None => (&text[..eq], false),
+        };
+        if !is_name(name) {
+            return None;
+        }
+        let mut value = Vec::new();
+        if eq + 1 < text.len() {
+            value.push(Part::Literal {
+                text: text[eq + 1..].to_string(),
+                quoted: false,
+            });
+        }
+        value.extend(rest.iter().cloned());
+        let value = Word {
+            parts: value,
+            span: self.span.start + eq + 1..self.span.end,
+        };
+        Some((name.to_string(), append, value))
+    }
+}
+
+const RESERVED_WORDS: &[&str] = &[
+    "if", "then", "elif", "else", "fi", "for", "while", "until", "do", "done", "case", "esac",
+];
+
+const DECLARATIONS: &[&str] = &["declare", "typeset", "local", "readonly", "export"];
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+fn brace_expand(parts: &[Part]) -> Vec<Vec<Part>> {
+    for (i, part) in parts.iter().enumerate() {
+        if let Part::Literal {
+            text,
+            quoted: false,
+        } = part
+        {
+            if let Some((start, end, alternatives)) = find_brace(text) {
+                return alternatives
+                    .iter()
+                    .flat_map(|alternative| {
+                        let mut expanded = parts.to_vec();
+                        expanded[i] = Part::Literal {
+                            text: format!("{}{}{}", &text[..start], alternative, &text[end..]),
+                            quoted: false,
+                        };
+                        brace_expand(&expanded)
+                    })
+                    .collect();
+            }
+        }
+    }
+    vec![parts.to_vec()]
+}
+
+fn find_brace(text: &str) -> Option<(usize, usize, Vec<&str>)> {
+    let bytes = text.as_bytes();
+    for start in (0..bytes.len()).filter(|&i| bytes[i] == b'{') {
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        for (i, &b) in bytes.iter().enumerate().skip(start) {
+            match b {
+                b'{' => depth += 1,
+                b',' if depth == 1 => commas.push(i),
+                b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        if commas.is_empty() {
+                            break;
+                        }
+                        let bounds: Vec<usize> = std::iter::once(start)
+                            .chain(commas)
+                            .chain(std::iter::once(i))
+                            .collect();
+                        let alternatives =
+                            bounds.windows(2).map(|w| &text[w[0] + 1..w[1]]).collect();
+                        return Some((start, i + 1, alternatives));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        Some(('?', rest)) => !text.is_empty() && glob_match(rest, &text[1..]),
+        Some(('[', rest)) => match glob_class(rest) {
+            Some((matches, rest)) => match text.split_first() {
+                Some((c, text)) => matches(*c) && glob_match(rest, text),
+                None => false,
+            },
+            None => text.first() == Some(&'[') && glob_match(rest, &text[1..]),
+        },
+        Some(('\\', rest)) if !rest.is_empty() => {
+            text.first() == Some(&rest[0]) && glob_match(&rest[1..], &text[1..])
+        }
+        Some((c, rest)) => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn glob_class(pattern: &[char]) -> Option<(Box<dyn Fn(char) -> bool + '_>, &[char])> {
+    let (negate, body) = match pattern.first() {
+        Some('!') | Some('^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    let end = body.iter().skip(1).position(|&c| c == ']')? + 1;
+    let class = &body[..end];
+    let matches = move |c: char| {
+        let mut i = 0;
+        let mut found = false;
+        while i < class.len() {
+            if i + 2 < class.len() && class[i + 1] == '-' {
+                found |= class[i] <= c && c <= class[i + 2];
+                i += 3;
+            } else {
+                found |= class[i] == c;
+                i += 1;
+            }
+        }
+        found != negate
+    };
+    Some((Box::new(matches), &body[end + 1..]))
+}
+
+#[derive(Debug)]
+enum Token {
+    Word(Word),
+    Op(&'static str),
+    Newline,
+    Eof,
+}
+
+const OPERATORS: &[&str] = &[
+    "&>>", "<<<", "<<-", ";;&", "&&", "||", ";;", ";&", "<<", ">>", "<&", ">&", "<>", ">|", "&>",
+    ";", "&", "|", "(", ")", "<", ">",
+];
+
+fn is_meta(b: u8) -> bool {
+    matches!(
+        b,
+        b' ' | b'\t' | b'\r' | b'\n' | b';' | b'&' | b'|' | b'(' | b')' | b'<' | b'>'
+    )
+}
+
+fn flush(parts: &mut Vec<Part>, literal: &mut Vec<u8>, quoted: bool) {
+    if !literal.is_empty() {
+        parts.push(Part::Literal {
+            text: String::from_utf8_lossy(literal).into_owned(),
+            quoted,
+        });
+        literal.clear();
+    }
+}
+
+struct Lexer<'a> {
+    src: &'a [u8],
+    pos: usize,
+    heredocs: Vec<(String, bool)>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a [u8]) -> Lexer<'a> {
+        Lexer {
+            src,
+            pos: 0,
+            heredocs: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.src.get(self.pos + offset).copied()
+    }
+
+    fn text(&self, start: usize, end: usize) -> String {
+        String::from_utf8_lossy(&self.src[start..end]).into_owned()
+    }
+
+    fn error(&self, message: &str) -> Error {
+        let pos = self.pos.min(self.src.len());
+        let line = self.src[..pos].iter().filter(|&&b| b == b'\n').count() + 1;
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("PKGBUILD line {}: {}", line, message),
+        )
+    }
+
+    fn skip_blanks(&mut self) {
+        loop {
+            match self.peek() {
+                Some(b' ') | Some(b'\t') | Some(b'\r') => self.pos += 1,
+                Some(b'\\') if self.peek_at(1) == Some(b'\n') => self.pos += 2,
+                Some(b'\\') if self.peek_at(1) == Some(b'\r') && self.peek_at(2) == Some(b'\n') => {
+                    self.pos += 3
+                }
+                Some(b'#') => {
+                    while !matches!(self.peek(), None | Some(b'\n')) {
+                        self.pos += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token> {
+        self.skip_blanks();
+        match self.peek() {
+            None => return Ok(Token::Eof),
+            Some(b'\n') => {
+                self.pos += 1;
+                self.heredoc_bodies();
+                return Ok(Token::Newline);
+            }
+            _ => {}
+        }
+        let rest = &self.src[self.pos..];
+        if let Some(&op) = OPERATORS.iter().find(|op| rest.starts_with(op.as_bytes())) {
+            self.pos += op.len();
+            if op == "<<" || op == "<<-" {
+                self.skip_blanks();
+                let delimiter = self.word(false)?;
+                let delimiter = delimiter
+                    .parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        Part::Literal { text, .. } => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                self.heredocs.push((delimiter, op == "<<-"));
+            }
+            return Ok(Token::Op(op));
+        }
+        self.word(false).map(Token::Word)
+    }
+
+    fn heredoc_bodies(&mut self) {
+        for (delimiter, strip_tabs) in std::mem::take(&mut self.heredocs) {
+            while self.pos < self.src.len() {
+                let end = self.src[self.pos..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .map_or(self.src.len(), |i| self.pos + i);
+                let mut line = &self.src[self.pos..end];
+                if let Some(stripped) = line.strip_suffix(b"\r") {
+                    line = stripped;
+                }
+                if strip_tabs {
+                    while let Some((b'\t', rest)) = line.split_first() {
+                        line = rest;
+                    }
+                }
+                self.pos = (end + 1).min(self.src.len());
+                if line == delimiter.as_bytes() {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn word(&mut self, raw: bool) -> Result<Word> {
+        let start = self.pos;
+        let mut parts = Vec::new();
+        let mut literal = Vec::new();
+        while let Some(b) = self.peek() {
+            if !raw && is_meta(b) {
+                break;
+            }
+            match b {
+                b'\\' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(b'\n') => self.pos += 1,
+                        Some(c) if c.is_ascii() => {
+                            flush(&mut parts, &mut literal, false);
+                            parts.push(Part::Literal {
+                                text: (c as char).to_string(),
+                                quoted: true,
+                            });
+                            self.pos += 1;
+                        }
+                        Some(_) => {}
+                        None => literal.push(b'\\'),
+                    }
+                }
+                b'\'' => {
+                    flush(&mut parts, &mut literal, false);
+                    self.pos += 1;
+                    let start = self.pos;
+                    let len = self.src[start..]
+                        .iter()
+                        .position(|&b| b == b'\'')
+                        .ok_or_else(|| self.error("unterminated single quote"))?;
+                    parts.push(Part::Literal {
+                        text: self.text(start, start + len),
+                        quoted: true,
+                    });
+                    self.pos = start + len + 1;
+                }
+                b'"' => {
+                    flush(&mut parts, &mut literal, false);
+                    self.double_quoted(&mut parts)?;
+                }
+                b'$' if self.peek_at(1) == Some(b'"') => self.pos += 1,
+                b'$' => {
+                    flush(&mut parts, &mut literal, false);
+                    let part = self.dollar(false)?;
+                    parts.push(part);
+                }
+                b'`' => {
+                    flush(&mut parts, &mut literal, false);
+                    let part = self.backtick()?;
+                    parts.push(part);
+                }
+                _ => {
+                    literal.push(b);
+                    self.pos += 1;
+                }
+            }
+        }
+        flush(&mut parts, &mut literal, false);
+        Ok(Word {
+            parts,
+            span: start..self.pos,
+        })
+    }
+
+    fn double_quoted(&mut self, parts: &mut Vec<Part>) -> Result<()> {
+        self.pos += 1;
+        let mut literal = Vec::new();
+        while let Some(b) = self.peek() {
+            match b {
+                b'"' => {
+                    self.pos += 1;
+                    parts.push(Part::Literal {
+                        text: String::from_utf8_lossy(&literal).into_owned(),
+                        quoted: true,
+                    });
+                    return Ok(());
+                }
+                b'\\' => {
+                    match self.peek_at(1) {
+                        Some(b'\n') => {}
+                        Some(c) if matches!(c, b'$' | b'`' | b'"' | b'\\') => literal.push(c),
+                        _ => {
+                            literal.push(b'\\');
+                            self.pos += 1;
+                            continue;
+                        }
+                    }
+                    self.pos += 2;
+                }
+                b'$' => {
+                    flush(parts, &mut literal, true);
+                    let part = self.dollar(true)?;
+                    parts.push(part);
+                }
+                b'`' => {
+                    flush(parts, &mut literal, true);
+                    let part = self.backtick()?;
+                    parts.push(part);
+                }
+                _ => {
+                    literal.push(b);
+                    self.pos += 1;
+                }
+            }
+        }
+        Err(self.error("unterminated double quote"))
+    }
+
+    fn dollar(&mut self, quoted: bool) -> Result<Part> {
+        let start = self.pos;
+        match self.peek_at(1) {
+            Some(b'{') => {
+                self.pos += 2;
+                self.skip_nested(b'{', b'}')?;
+                let raw = self.text(start + 2, self.pos - 1);
+                let param = Param::parse(&raw)
+                    .ok_or_else(|| self.error(&format!("bad substitution `${{{}}}`", raw)))?;
+                Ok(Part::Param { param, quoted })
+            }
+            Some(b'(') => {
+                self.pos += 2;
+                self.skip_nested(b'(', b')')?;
+                Ok(Part::Command(self.text(start, self.pos)))
+            }
+            Some(b'\'') if !quoted => self.ansi_c_quoted(),
+            Some(c) if c == b'_' || c.is_ascii_alphabetic() => {
+                self.pos += 1;
+                while matches!(self.peek(), Some(c) if c == b'_' || c.is_ascii_alphanumeric()) {
+                    self.pos += 1;
+                }
+                Ok(Part::Param {
+                    param: Param::simple(self.text(start + 1, self.pos)),
+                    quoted,
+                })
+            }
+            Some(c) if c.is_ascii_digit() || b"@*#?$!-".contains(&c) => {
+                self.pos += 2;
+                Ok(Part::Param {
+                    param: Param::simple((c as char).to_string()),
+                    quoted,
+                })
+            }
+            _ => {
+                self.pos += 1;
+                Ok(Part::Literal {
+                    text: "$".to_string(),
+                    quoted,
+                })
+            }
+        }
+    }
+
+    fn ansi_c_quoted(&mut self) -> Result<Part> {
+        self.pos += 2;
+        let mut literal = Vec::new();
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'\'' => {
+                    return Ok(Part::Literal {
+                        text: String::from_utf8_lossy(&literal).into_owned(),
+                        quoted: true,
+                    })
+                }
+                b'\\' => {
+                    let escaped = self.peek().unwrap_or(b'\\');
+                    self.pos += 1;
+                    literal.push(match escaped {
+                        b'n' => b'\n',
+                        b't' => b'\t',
+                        b'r' => b'\r',
+                        b'a' => 0x07,
+                        b'e' | b'E' => 0x1b,
+                        c => c,
+                    });
+                }
+                _ => literal.push(b),
+            }
+        }
+        Err(self.error("unterminated $'...' quote"))
+    }
+
+    fn backtick(&mut self) -> Result<Part> {
+        let start = self.pos;
+        self.pos += 1;
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'\\' => self.pos += 1,
+                b'`' => return Ok(Part::Command(self.text(start, self.pos))),
+                _ => {}
+            }
+        }
+        Err(self.error("unterminated backtick"))
+    }
+
+    fn skip_dollar(&mut self) -> Result<()> {
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                self.skip_nested(b'(', b')')
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                self.skip_nested(b'{', b'}')
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn skip_double_quoted(&mut self) -> Result<()> {
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'"' => return Ok(()),
+                b'\\' => self.pos += 1,
+                b'$' => self.skip_dollar()?,
+                b'`' => {
+                    self.pos -= 1;
+                    self.backtick()?;
+                }
+                _ => {}
+            }
+        }
+        Err(self.error("unterminated double quote"))
+    }
+
+    fn skip_nested(&mut self, open: u8, close: u8) -> Result<()> {
+        let mut depth = 1;
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'\\' => self.pos += 1,
+                b'\'' => match self.src[self.pos..].iter().position(|&b| b == b'\'') {
+                    Some(len) => self.pos += len + 1,
+                    None => break,
+                },
+                b'"' => self.skip_double_quoted()?,
+                b'`' => {
+                    self.pos -= 1;
+                    self.backtick()?;
+                }
+                b'$' => self.skip_dollar()?,
+                b if b == open => depth += 1,
+                b if b == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(self.error(&format!("missing closing `{}`", close as char)))
+    }
+}
+
+enum AssignedValue {
+    Scalar(Word),
+    Array(Vec<Word>),
+}
+
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    peeked: Option<Token>,
+    parsed: Parsed,
+    /// Nesting of `if`, `case` and loop bodies.
+    depth: usize,
+    /// Whether the next command follows `&&` or `||`.
+    and_or: bool,
+    /// The package function whose body is being parsed.
+    function: Option<String>,
+    /// The assignments in package functions, evaluated once all globals are known.
+    function_assignments: Vec<(String, String, bool, AssignedValue)>,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Result<Token> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.lexer.next_token(),
+        }
+    }
+
+    fn program(&mut self) -> Result<()> {
+        loop {
+            match self.next()? {
+                Token::Eof => return Ok(()),
+                Token::Word(word) => self.command(word)?,
+                Token::Op("(") => self.skip_until("(", ")")?,
+                Token::Op("&&") | Token::Op("||") => self.and_or = true,
+                Token::Newline | Token::Op(_) => {}
+            }
+        }
+    }
+
+    fn command(&mut self, mut word: Word) -> Result<()> {
+        let and_or = std::mem::take(&mut self.and_or);
+        if let Some(reserved) = RESERVED_WORDS
+            .iter()
+            .find(|reserved| word.is_literal(reserved))
+        {
+            return self.reserved_word(reserved);
+        }
+        let conditional = and_or || self.depth > 0;
+        let declaration = DECLARATIONS.iter().any(|builtin| word.is_literal(builtin));
+        let start = word.span.start;
+        let mut end;
+        let mut bare_names = 0;
+        let mut assignments = Vec::new();
+        loop {
+            let word_start = word.span.start;
+            match word.assignment() {
+                Some((name, append, value)) => {
+                    let is_array = value.parts.is_empty() && self.lexer.peek() == Some(b'(');
+                    let (span, elements, value) = if is_array {
+                        self.lexer.pos += 1;
+                        let words = self.array()?;
+                        (
+                            value.span.start..self.lexer.pos,
+                            words.iter().map(|word| word.span.clone()).collect(),
+                            AssignedValue::Array(words),
+                        )
+                    } else {
+                        (value.span.clone(), Vec::new(), AssignedValue::Scalar(value))
+                    };
+                    end = span.end;
+                    assignments.push((
+                        Assignment {
+                            name,
+                            append,
+                            conditional,
+                            statement: word_start..span.end,
+                            value: span,
+                            elements,
+                            values: None,
+                        },
+                        value,
+                    ));
+                }
+                // The builtin itself, options and bare names of `declare -a foo=(...)` etc.
+                None if declaration => {
+                    end = word.span.end;
+                    let option = matches!(
+                        word.parts.first(),
+                        Some(Part::Literal { text, quoted: false }) if text.starts_with(['-', '+'])
+                    );
+                    if word_start != start && !option {
+                        bare_names += 1;
+                    }
+                }
+                None => return self.skip_command(word),
+            }
+            match self.next()? {
+                Token::Word(next) => word = next,
+                token => {
+                    self.peeked = Some(token);
+                    break;
+                }
+            }
+        }
+        if declaration && bare_names == 0 && assignments.len() == 1 {
+            assignments[0].0.statement = start..end;
+        }
+        for (mut assignment, value) in assignments {
+            if let Some(function) = &self.function {
+                self.function_assignments.push((
+                    function.clone(),
+                    assignment.name,
+                    assignment.append,
+                    value,
+                ));
+                continue;
+            }
+            assignment.values = self.assign(&assignment, value);
+            self.parsed.assignments.push(assignment);
+        }
+        Ok(())
+    }
+
+    /// Tracks compound commands so the commands in their bodies are parsed like top-level ones.
+    fn reserved_word(&mut self, reserved: &str) -> Result<()> {
+        match reserved {
+            "if" | "while" | "until" => self.depth += 1,
+            "for" => {
+                self.depth += 1;
+                self.skip_simple_command()?;
+            }
+            "case" => {
+                self.depth += 1;
+                self.case_body()?;
+            }
+            "fi" | "done" | "esac" => self.depth = self.depth.saturating_sub(1),
+            // `then`, `elif`, `else` and `do` are followed by a command.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn case_body(&mut self) -> Result<()> {
+        loop {
+            match self.next()? {
+                Token::Word(word) if word.is_literal("in") => break,
+                Token::Eof => return Err(self.lexer.error("expected `in` after `case`")),
+                _ => {}
+            }
+        }
+        loop {
+            // The pattern, up to its closing parenthesis.
+            loop {
+                match self.next()? {
+                    Token::Word(word) if word.is_literal("esac") => {
+                        self.depth = self.depth.saturating_sub(1);
+                        return Ok(());
+                    }
+                    Token::Op(")") => break,
+                    Token::Eof => return Err(self.lexer.error("missing `esac`")),
+                    _ => {}
+                }
+            }
+            loop {
+                match self.next()? {
+                    Token::Op(";;") | Token::Op(";&") | Token::Op(";;&") => break,
+                    Token::Word(word) if word.is_literal("esac") => {
+                        self.depth = self.depth.saturating_sub(1);
+                        return Ok(());
+                    }
+                    Token::Word(word) => self.command(word)?,
+                    Token::Op("(") => self.skip_until("(", ")")?,
+                    Token::Op("&&") | Token::Op("||") => self.and_or = true,
+                    Token::Eof => return Err(self.lexer.error("missing `esac`")),
+                    Token::Newline | Token::Op(_) => {}
+                }
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Vec<Word>> {
+        let mut words = Vec::new();
+        loop {
+            match self.lexer.next_token()? {
+                Token::Word(word) => words.push(word),
+                Token::Newline => {}
+                Token::Op(")") => return Ok(words),
+                Token::Eof => return Err(self.lexer.error("unterminated array")),
+                Token::Op(op) => {
+                    return Err(self.lexer.error(&format!("unexpected `{}` in array", op)))
+                }
+            }
+        }
+    }
+
+    /// Evaluates an assignment into the variables and returns the values it assigns itself.
+    fn assign(&mut self, assignment: &Assignment, value: AssignedValue) -> Option<Vec<String>> {
+        let name = assignment.name.clone();
+        let append = assignment.append;
+        if append && self.parsed.unsupported.contains_key(&name) {
+            return None;
+        }
+        let value = match &value {
+            AssignedValue::Scalar(word) => self.parsed.expand_scalar(word).map(Value::Scalar),
+            AssignedValue::Array(words) => self.parsed.expand_array(words).map(Value::Array),
+        };
+        match value {
+            Ok(value) => {
+                let values = value.values().to_vec();
+                let value = match self.parsed.vars.remove(&name) {
+                    Some(old) if append => old.append(value),
+                    _ => value,
+                };
+                self.parsed.vars.insert(name, value);
+                Some(values)
+            }
+            Err(e) => {
+                self.parsed.vars.remove(&name);
+                self.parsed.unsupported.insert(name, e.to_string());
+                None
+            }
+        }
+    }
+
+    fn skip_command(&mut self, first: Word) -> Result<()> {
+        if first.is_literal("{") {
+            return self.skip_until("{", "}");
+        }
+        let mut name = first.clone();
+        if first.is_literal("function") {
+            if let Token::Word(word) = self.next()? {
+                name = word;
+            }
+        }
+        match self.next()? {
+            Token::Op("(") => match self.next()? {
+                Token::Op(")") => self.function_body(&name),
+                token => {
+                    self.peeked = Some(token);
+                    self.skip_until("(", ")")
+                }
+            },
+            Token::Word(word) if first.is_literal("function") && word.is_literal("{") => {
+                self.braced_body(&name)
+            }
+            token => {
+                self.peeked = Some(token);
+                self.skip_simple_command()
+            }
+        }
+    }
+
+    fn function_body(&mut self, name: &Word) -> Result<()> {
+        loop {
+            match self.next()? {
+                Token::Newline => {}
+                Token::Word(word) if word.is_literal("{") => return self.braced_body(name),
+                Token::Op("(") => return self.skip_until("(", ")"),
+                Token::Word(word) => return self.skip_command(word),
+                _ => return Err(self.lexer.error("expected function body")),
+            }
+        }
+    }
+
+    /// Parses the assignments in the body of a package function `name` after its `{`, or
+    /// skips the body of any other function. A body that cannot be parsed is skipped as well.
+    fn braced_body(&mut self, name: &Word) -> Result<()> {
+        let function = match name.parts.as_slice() {
+            [Part::Literal {
+                text,
+                quoted: false,
+            }] if text.starts_with("package_") => text.clone(),
+            _ => return self.skip_until("{", "}"),
+        };
+        let (pos, heredocs) = (self.lexer.pos, self.lexer.heredocs.clone());
+        let assigned = self.function_assignments.len();
+        let depth = std::mem::take(&mut self.depth);
+        self.function = Some(function);
+        let result = self.package_body();
+        self.function = None;
+        self.depth = depth;
+        if result.is_err() {
+            self.lexer.pos = pos;
+            self.lexer.heredocs = heredocs;
+            self.peeked = None;
+            self.function_assignments.truncate(assigned);
+            return self.skip_until("{", "}");
+        }
+        result
+    }
+
+    fn package_body(&mut self) -> Result<()> {
+        loop {
+            match self.next()? {
+                Token::Eof => return Err(self.lexer.error("missing closing `}`")),
+                Token::Word(word) if word.is_literal("}") => return Ok(()),
+                Token::Word(word) => self.command(word)?,
+                Token::Op("(") => self.skip_until("(", ")")?,
+                Token::Op("&&") | Token::Op("||") => self.and_or = true,
+                Token::Newline | Token::Op(_) => {}
+            }
+        }
+    }
+
+    fn skip_until(&mut self, open: &str, close: &str) -> Result<()> {
+        let mut depth = 1;
+        loop {
+            let (is_open, is_close) = match self.next()? {
+                Token::Eof => {
+                    return Err(self.lexer.error(&format!("missing closing `{}`", close)))
+                }
+                Token::Word(word) => (word.is_literal(open), word.is_literal(close)),
+                Token::Op(op) => (op == open, op == close),
+                Token::Newline => (false, false),
+            };
+            if is_open {
+                depth += 1;
+            } else if is_close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn skip_simple_command(&mut self) -> Result<()> {
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                Token::Eof => return Ok(()),
+                // Left for the caller, which may be in a list or a `case` item.
+                token @ Token::Newline
+                | token @ Token::Op(";")
+                | token @ Token::Op("&")
+                | token @ Token::Op("&&")
+                | token @ Token::Op("||")
+                | token @ Token::Op("|")
+                | token @ Token::Op(";;")
+                | token @ Token::Op(";&")
+                | token @ Token::Op(";;&")
+                    if depth == 0 =>
+                {
+                    self.peeked = Some(token);
+                    return Ok(());
+                }
+                Token::Op("(") => depth += 1,
+                Token::Op(")") if depth > 0 => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! OpenPGP keys for `validpgpkeys`, and the signatures made with them. Keys are read from
+//! `keys/pgp` or fetched from the keyserver into memory, never into the user's keyring.
+
+use crate::download::Downloader;
+use crate::logging;
+use openpgp::cert::amalgamation::ValidAmalgamation;
+use openpgp::parse::stream::{
+    DetachedVerifierBuilder, GoodChecksum, MessageLayer, MessageStructure, VerificationError,
+    VerificationHelper,
+};
+use openpgp::parse::Parse;
+use openpgp::policy::StandardPolicy;
+use openpgp::types::RevocationStatus;
+use openpgp::{Cert, KeyHandle};
+use sequoia_openpgp as openpgp;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::process::Command;
+use tracing::debug;
+
+/// Canonicalizes a full v4 or v5 key fingerprint, which makepkg requires in `validpgpkeys`:
+/// uppercase hex without spaces or a `0x` prefix.
+pub fn normalize_fingerprint(fingerprint: &str) -> Result<String> {
+    let hex: String = fingerprint
+        .trim()
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase();
+    if (hex.len() == 40 || hex.len() == 64) && hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(hex)
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("`{}` is not a full key fingerprint", fingerprint),
+        ))
+    }
+}
+
+/// gpg's keyserver when dirmngr has none configured.
+const DEFAULT_KEYSERVER: &str = "hkps://keys.openpgp.org";
+
+/// Where AUR packages keep the keys of validpgpkeys, as `<fingerprint>.asc`.
+const KEYS_DIR: &str = "keys/pgp";
+
+/// The keyserver gpg is configured to fetch missing keys from.
+pub fn keyserver() -> String {
+    let output = logging::command(Command::new("gpgconf").args(["--list-options", "dirmngr"]))
+        .output()
+        .ok()
+        .filter(|output| output.status.success());
+    let stdout = output.map_or_else(String::new, |output| {
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    });
+    // gpgconf's fields: name:flags:level:description:type:alt-type:argname:default:argdef:value
+    let fields = stdout
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&"keyserver"));
+    fields
+        .and_then(|fields| {
+            fields
+                .get(9)
+                .into_iter()
+                .chain(fields.get(7))
+                .map(|value| value.trim_start_matches('"').replace("%3a", ":"))
+                .find(|value| !value.is_empty())
+        })
+        .unwrap_or_else(|| DEFAULT_KEYSERVER.to_string())
+}
+
+/// The URL of the key with `fingerprint` on `keyserver`, over the HKP lookup API.
+fn lookup_url(keyserver: &str, fingerprint: &str) -> String {
+    let base = if let Some(host) = keyserver.strip_prefix("hkps://") {
+        format!("https://{}", host)
+    } else if let Some(host) = keyserver.strip_prefix("hkp://") {
+        // HKP's own port, unless the keyserver names one.
+        if host.trim_end_matches('/').contains(':') {
+            format!("http://{}", host)
+        } else {
+            format!("http://{}:11371", host.trim_end_matches('/'))
+        }
+    } else {
+        keyserver.to_string()
+    };
+    format!(
+        "{}/pks/lookup?op=get&options=mr&search=0x{}",
+        base.trim_end_matches('/'),
+        fingerprint
+    )
+}
+
+/// The key with `fingerprint` from `keys/pgp`, or else from the keyserver through `downloader`
+/// if there is one.
+fn cert(fingerprint: &str, downloader: Option<&Downloader>) -> Result<Cert> {
+    let path = Path::new(KEYS_DIR).join(format!("{}.asc", fingerprint));
+    let (cert, origin) = if path.exists() {
+        (Cert::from_file(&path), path.display().to_string())
+    } else if let Some(downloader) = downloader {
+        let keyserver = keyserver();
+        let key = downloader
+            .fetch_text(&lookup_url(&keyserver, fingerprint))?
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("{} is not on {}", fingerprint, keyserver),
+                )
+            })?;
+        (Cert::from_bytes(key.as_bytes()), keyserver)
+    } else {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} is not in {}", fingerprint, KEYS_DIR),
+        ));
+    };
+    let cert = cert.map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("bad key {} from {}: {:#}", fingerprint, origin, e),
+        )
+    })?;
+    if cert.fingerprint().to_hex() != fingerprint {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} has key {} instead of {}",
+                origin,
+                cert.fingerprint().to_hex(),
+                fingerprint
+            ),
+        ));
+    }
+    Ok(cert)
+}
+
+/// Returns the user IDs of a key, fetching it through `downloader` if there is one and it is
+/// not in `keys/pgp`.
+pub fn fetch_uids(fingerprint: &str, downloader: Option<&Downloader>) -> Result<Vec<String>> {
+    Ok(cert(fingerprint, downloader)?
+        .userids()
+        .map(|uid| String::from_utf8_lossy(uid.userid().value()).into_owned())
+        .collect())
+}
+
+/// Verifies the detached `signature` of `file` and returns the fingerprint of the key that made
+/// it, which must be one of `keys`. Keys not in `keys/pgp` are fetched through `downloader` if
+/// there is one.
+pub fn verify(
+    signature: &Path,
+    file: &Path,
+    keys: &[String],
+    downloader: Option<&Downloader>,
+) -> Result<String> {
+    let mut keyring = Keyring {
+        keys,
+        certs: Vec::new(),
+        complete: true,
+        outcome: None,
+    };
+    for key in keys {
+        match cert(key, downloader) {
+            Ok(cert) => keyring.certs.push(cert),
+            Err(e) => {
+                debug!("{}", e);
+                keyring.complete = false;
+            }
+        }
+    }
+    let policy = StandardPolicy::new();
+    let invalid = |e| Error::new(ErrorKind::InvalidData, format!("{:#}", e));
+    let mut verifier = DetachedVerifierBuilder::from_file(signature)
+        .map_err(invalid)?
+        .with_policy(&policy, None, keyring)
+        .map_err(invalid)?;
+    verifier.verify_file(file).map_err(invalid)?;
+    verifier
+        .into_helper()
+        .outcome
+        .unwrap_or_else(|| Err(Error::new(ErrorKind::InvalidData, "no valid signature")))
+}
+
+/// The keys of validpgpkeys a signature is checked against, and what the check found.
+struct Keyring<'a> {
+    keys: &'a [String],
+    certs: Vec<Cert>,
+    /// Whether every key of `keys` is in `certs`.
+    complete: bool,
+    outcome: Option<Result<String>>,
+}
+
+impl Keyring<'_> {
+    /// The signer of the first good signature, or else the first problem.
+    fn outcome(
+        &self,
+        results: &[std::result::Result<GoodChecksum, VerificationError>],
+    ) -> Result<String> {
+        let mut problem = None;
+        for result in results {
+            let error = match result {
+                Ok(GoodChecksum { ka, .. }) => {
+                    // The signing (sub)key, or the primary key it belongs to.
+                    let signer = [ka.key().fingerprint(), ka.cert().fingerprint()]
+                        .iter()
+                        .map(|fingerprint| fingerprint.to_hex())
+                        .find(|fingerprint| self.keys.contains(fingerprint));
+                    return signer.ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::PermissionDenied,
+                            format!(
+                                "signed by {}, which is not in validpgpkeys",
+                                ka.cert().fingerprint().to_hex()
+                            ),
+                        )
+                    });
+                }
+                Err(VerificationError::MissingKey { sig }) if self.complete => Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!(
+                        "signed by {}, which is not in validpgpkeys",
+                        sig.get_issuers()
+                            .first()
+                            .map_or_else(|| "an unknown key".to_string(), KeyHandle::to_hex)
+                    ),
+                ),
+                Err(VerificationError::MissingKey { .. }) => {
+                    Error::new(ErrorKind::InvalidData, "the signing key is not available")
+                }
+                Err(VerificationError::BadKey { ka, error, .. }) => {
+                    let revoked =
+                        |status: RevocationStatus| matches!(status, RevocationStatus::Revoked(_));
+                    let problem = if revoked(ka.revocation_status())
+                        || revoked(ka.cert().revocation_status())
+                    {
+                        "the signing key was revoked".to_string()
+                    } else if ka.alive().is_err() || ka.cert().alive().is_err() {
+                        "the signing key expired".to_string()
+                    } else {
+                        format!("the signing key is not valid: {:#}", error)
+                    };
+                    Error::new(ErrorKind::InvalidData, problem)
+                }
+                Err(VerificationError::BadSignature { .. }) => {
+                    Error::new(ErrorKind::InvalidData, "bad signature")
+                }
+                Err(e) => Error::new(
+                    ErrorKind::InvalidData,
+                    format!("no valid signature: {:#}", e),
+                ),
+            };
+            problem.get_or_insert(error);
+        }
+        Err(problem.unwrap_or_else(|| Error::new(ErrorKind::InvalidData, "no valid signature")))
+    }
+}
+
+impl VerificationHelper for Keyring<'_> {
+    fn get_certs(&mut self, _ids: &[KeyHandle]) -> openpgp::Result<Vec<Cert>> {
+        Ok(self.certs.clone())
+    }
+
+    fn check(&mut self, structure: MessageStructure) -> openpgp::Result<()> {
+        for layer in structure.into_iter() {
+            if let MessageLayer::SignatureGroup { results } = layer {
+                self.outcome = Some(self.outcome(&results));
+                break;
+            }
+        }
+        Ok(())
+    }
+}
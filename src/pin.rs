@@ -0,0 +1,173 @@
+//! Certificate pinning: checks the certificate a server presented against the SHA-256
+//! fingerprints configured for its host, of the whole certificate or of its public key. The
+//! check is part of the TLS handshake, so nothing is sent to a server that fails it.
+
+use crate::config::Pin;
+use crate::download::host_matches;
+use digest::Digest;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::Sha256;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+
+/// The TLS configuration of a client that verifies certificates against the system's CA
+/// bundle like any other, and those of the hosts in `pins` against their pins as well.
+pub fn tls_config(pins: &[Pin], http2: bool) -> Result<rustls::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let bundle = openssl_probe::probe().cert_file.ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            "no CA certificates found to verify the pinned hosts with",
+        )
+    })?;
+    let bundle = std::fs::read(&bundle)
+        .map_err(|e| Error::new(e.kind(), format!("{}: {}", bundle.display(), e)))?;
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(CertificateDer::pem_slice_iter(&bundle).filter_map(|c| c.ok()));
+    let verifier = PinVerifier {
+        roots: WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()
+            .map_err(Error::other)?,
+        pins: pins
+            .iter()
+            .map(|pin| Pin {
+                host: pin.host.to_ascii_lowercase(),
+                ..pin.clone()
+            })
+            .collect(),
+    };
+    let mut config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    config.alpn_protocols = if http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    Ok(config)
+}
+
+/// Verifies the chain of every server with the CA certificates, and then the certificate of
+/// pinned hosts against their pins.
+#[derive(Debug)]
+struct PinVerifier {
+    roots: Arc<WebPkiServerVerifier>,
+    /// With lowercase hosts.
+    pins: Vec<Pin>,
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        intermediates: &[CertificateDer],
+        server_name: &ServerName,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let verified = self.roots.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let host = server_name.to_str().to_ascii_lowercase();
+        match self.pins.iter().find(|pin| host_matches(&host, &pin.host)) {
+            // Not `InvalidCertificate`, which shows its own errors by their `Debug`.
+            Some(pin) if !matches(pin, end_entity) => Err(rustls::Error::General(format!(
+                "{} presented a certificate that does not match its pins",
+                host
+            ))),
+            _ => Ok(verified),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.roots.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.roots.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.roots.supported_verify_schemes()
+    }
+}
+
+/// Whether the DER `certificate` has one of the fingerprints of `pin`.
+pub fn matches(pin: &Pin, certificate: &[u8]) -> bool {
+    let fingerprint = hex::encode(Sha256::digest(certificate));
+    let public_key = public_key(certificate).map(|spki| hex::encode(Sha256::digest(spki)));
+    pin.certificates
+        .iter()
+        .any(|pinned| normalize(pinned) == fingerprint)
+        || pin
+            .public_keys
+            .iter()
+            .any(|pinned| Some(normalize(pinned)) == public_key)
+}
+
+/// A fingerprint in lowercase hex, without the colons `openssl x509 -fingerprint` puts in.
+fn normalize(fingerprint: &str) -> String {
+    fingerprint.replace(':', "").to_ascii_lowercase()
+}
+
+/// The SubjectPublicKeyInfo of a DER certificate, header included, which is what public key
+/// pins are the hash of.
+fn public_key(certificate: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = element(certificate)?;
+    let (_, mut tbs, _) = element(certificate)?;
+    let (tag, _, rest) = element(tbs)?;
+    // The version is optional and explicitly tagged.
+    if tag == 0xa0 {
+        tbs = rest;
+    }
+    // Then the serial number, signature algorithm, issuer, validity and subject.
+    for _ in 0..5 {
+        tbs = element(tbs)?.2;
+    }
+    let (_, _, rest) = element(tbs)?;
+    Some(&tbs[..tbs.len() - rest.len()])
+}
+
+/// Splits the DER element at the start of `der` into its tag, its content and what follows it.
+fn element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, der) = der.split_first()?;
+    let (&first, mut der) = der.split_first()?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let octets = usize::from(first & 0x7f);
+        if octets == 0 || octets > std::mem::size_of::<usize>() || der.len() < octets {
+            return None;
+        }
+        let (len, rest) = der.split_at(octets);
+        der = rest;
+        len.iter()
+            .fold(0, |len, &octet| (len << 8) | usize::from(octet))
+    };
+    if der.len() < len {
+        return None;
+    }
+    let (content, rest) = der.split_at(len);
+    Some((tag, content, rest))
+}
@@ -0,0 +1,533 @@
+use crate::parser;
+use std::io::{Result, Write};
+use std::ops::Range;
+use std::path::Path;
+use tempfile::NamedTempFile;
+use tracing::debug;
+
+/// The PKGBUILD as raw bytes, so edits leave line endings, a missing final newline and non-UTF-8
+/// text (e.g. in maintainer comments) as they were.
+#[derive(Debug)]
+pub struct Pkgbuild {
+    pub content: Vec<u8>,
+}
+
+impl Pkgbuild {
+    pub fn new() -> Result<Pkgbuild> {
+        Ok(Pkgbuild {
+            content: std::fs::read("PKGBUILD")?,
+        })
+    }
+
+    /// Replaces the PKGBUILD with the content, keeping its permissions, and the old one at
+    /// `backup` if given. The content goes to a temporary file next to it first, which is renamed
+    /// over it, so an interrupted write never leaves half a PKGBUILD behind.
+    pub fn write(&self, backup: Option<&Path>) -> Result<()> {
+        // Through a symlink, the file it points to is the one to replace.
+        let path = std::fs::canonicalize("PKGBUILD")?;
+        let permissions = std::fs::metadata(&path)?.permissions();
+        let mut file = NamedTempFile::new_in(path.parent().unwrap_or_else(|| Path::new(".")))?;
+        file.write_all(&self.content)?;
+        file.as_file().set_permissions(permissions)?;
+        file.as_file().sync_all()?;
+        if let Some(backup) = backup {
+            std::fs::copy(&path, backup)?;
+        }
+        file.persist(&path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    fn text(&self, range: Range<usize>) -> String {
+        String::from_utf8_lossy(&self.content[range]).into_owned()
+    }
+
+    fn splice(&mut self, range: Range<usize>, replacement: &str) {
+        debug!(
+            "replacing `{}` with `{}`",
+            String::from_utf8_lossy(&self.content[range.clone()]),
+            replacement
+        );
+        self.content.splice(range, replacement.bytes());
+    }
+
+    /// The line ending new lines get, CRLF if the file already uses it.
+    fn newline(&self) -> &'static str {
+        if self.content.windows(2).any(|pair| pair == b"\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        }
+    }
+
+    fn line_start(&self, pos: usize) -> usize {
+        self.content[..pos]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1)
+    }
+
+    /// Returns the position of the `\n` ending the line of `pos`, or the end of the file.
+    fn line_end(&self, pos: usize) -> usize {
+        self.content[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(self.content.len(), |i| pos + i)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        parser::parse(&self.content)?.scalar(key)
+    }
+
+    pub fn array(&self, key: &str) -> Result<Vec<String>> {
+        parser::parse(&self.content)?.array(key)
+    }
+
+    /// The values of `key` as written by each of its assignments, see `Parsed::segments`.
+    pub fn segments(&self, key: &str) -> Result<Vec<Vec<String>>> {
+        parser::parse(&self.content)?.segments(key)
+    }
+
+    /// Whether some assignment to `key` only runs in some branches, see `Parsed::conditional`.
+    pub fn conditional(&self, key: &str) -> Result<bool> {
+        Ok(parser::parse(&self.content)?.conditional(key))
+    }
+
+    pub fn elements(&self, key: &str) -> Result<Vec<String>> {
+        parser::parse(&self.content)?.elements(key)
+    }
+
+    /// Replaces the value of every top-level assignment to `key`, leaving the rest of the file
+    /// (comments, functions, other variables) untouched. Returns `false` if there was no such
+    /// assignment.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<bool> {
+        let parsed = parser::parse(&self.content)?;
+        let mut found = false;
+        for assignment in parsed
+            .assignments()
+            .iter()
+            .rev()
+            .filter(|assignment| assignment.name == key && !assignment.append)
+        {
+            self.splice(assignment.value.clone(), value);
+            found = true;
+        }
+        Ok(found)
+    }
+
+    /// Replaces every top-level assignment to `key` with an array of `values`, reusing the quoting
+    /// and, unless `layout` is given, the line layout of the array it replaces. Returns `false`
+    /// if there was no such assignment.
+    pub fn set_array(
+        &mut self,
+        key: &str,
+        values: &[String],
+        layout: Option<Layout>,
+    ) -> Result<bool> {
+        let parsed = parser::parse(&self.content)?;
+        let mut found = false;
+        for assignment in parsed
+            .assignments()
+            .iter()
+            .rev()
+            .filter(|assignment| assignment.name == key && !assignment.append)
+        {
+            let array = ArrayStyle::detect(&self.content, assignment, self.newline(), layout)
+                .format(values);
+            self.splice(assignment.value.clone(), &array);
+            found = true;
+        }
+        Ok(found)
+    }
+
+    /// Replaces the top-level assignments to `key`, appends and conditional ones included, with
+    /// one array of `segments` each. Returns `false` if `key` is not assigned exactly that many
+    /// times.
+    pub fn set_array_segments(
+        &mut self,
+        key: &str,
+        segments: &[Vec<String>],
+        layout: Option<Layout>,
+    ) -> Result<bool> {
+        let parsed = parser::parse(&self.content)?;
+        let assignments: Vec<_> = parsed
+            .assignments()
+            .iter()
+            .filter(|assignment| assignment.name == key)
+            .collect();
+        if assignments.len() != segments.len() {
+            return Ok(false);
+        }
+        for (assignment, values) in assignments.into_iter().zip(segments).rev() {
+            let array = ArrayStyle::detect(&self.content, assignment, self.newline(), layout)
+                .format(values);
+            self.splice(assignment.value.clone(), &array);
+        }
+        Ok(true)
+    }
+
+    /// Inserts a new `key=(values)` array below the last top-level assignment to `anchor`.
+    /// Returns `false` if there is no such assignment.
+    pub fn insert_array_after(
+        &mut self,
+        anchor: &str,
+        key: &str,
+        values: &[String],
+        layout: Option<Layout>,
+    ) -> Result<bool> {
+        let newline = self.newline();
+        let mut style = ArrayStyle::new(key.len() + 2, newline);
+        if let Some(layout) = layout {
+            style.apply(layout, key.len() + 2, "", newline);
+        }
+        let array = style.format(values);
+        self.insert_after(anchor, key, &array)
+    }
+
+    /// Removes every top-level assignment to `key`, including its line if nothing else is on it,
+    /// and the `declare` of it if that declares nothing else.
+    /// Returns `false` if there was no such assignment.
+    pub fn remove(&mut self, key: &str) -> Result<bool> {
+        let parsed = parser::parse(&self.content)?;
+        let mut found = false;
+        for assignment in parsed
+            .assignments()
+            .iter()
+            .rev()
+            .filter(|assignment| assignment.name == key)
+        {
+            let Range { mut start, mut end } = assignment.statement.clone();
+            let line_start = self.line_start(start);
+            let line_end = self.line_end(end);
+            if self.text(line_start..start).trim().is_empty()
+                && self.text(end..line_end).trim().is_empty()
+            {
+                start = line_start;
+                end = line_end + 1;
+                if end > self.content.len() {
+                    // Keep the file without a final newline.
+                    end = self.content.len();
+                    start = self.content[..start]
+                        .strip_suffix(b"\n")
+                        .map_or(start, |rest| rest.strip_suffix(b"\r").unwrap_or(rest).len());
+                }
+            }
+            self.splice(start..end, "");
+            found = true;
+        }
+        Ok(found)
+    }
+
+    /// Returns the raw text of the elements of the last top-level assignment to `key`.
+    pub fn raw_elements(&self, key: &str) -> Result<Vec<String>> {
+        let parsed = parser::parse(&self.content)?;
+        Ok(parsed
+            .assignments()
+            .iter()
+            .rev()
+            .find(|assignment| assignment.name == key && !assignment.append)
+            .map_or_else(Vec::new, |assignment| {
+                assignment
+                    .elements
+                    .iter()
+                    .map(|element| self.text(element.clone()))
+                    .collect()
+            }))
+    }
+
+    /// Inserts the raw text `element` after the element at `index` of the last top-level
+    /// assignment to `key`, separated like the array's other elements. Returns `false` if there
+    /// is no such element.
+    pub fn insert_element(&mut self, key: &str, index: usize, element: &str) -> Result<bool> {
+        let parsed = parser::parse(&self.content)?;
+        let assignment = match parsed
+            .assignments()
+            .iter()
+            .rev()
+            .find(|assignment| assignment.name == key && !assignment.append)
+        {
+            Some(assignment) => assignment,
+            None => return Ok(false),
+        };
+        let end = match assignment.elements.get(index) {
+            Some(previous) => previous.end,
+            None => return Ok(false),
+        };
+        let separator =
+            ArrayStyle::detect(&self.content, assignment, self.newline(), None).separator;
+        self.splice(end..end, &format!("{}{}", separator, element));
+        Ok(true)
+    }
+
+    /// Rewrites the raw text of individual elements of the top-level arrays whose name matches
+    /// `key`. `rewrite` returns `None` to leave an element untouched.
+    pub fn rewrite_elements(
+        &mut self,
+        key: impl Fn(&str) -> bool,
+        mut rewrite: impl FnMut(&str) -> Option<String>,
+    ) -> Result<()> {
+        let parsed = parser::parse(&self.content)?;
+        let mut elements: Vec<_> = parsed
+            .assignments()
+            .iter()
+            .filter(|assignment| key(&assignment.name))
+            .flat_map(|assignment| assignment.elements.iter().cloned())
+            .collect();
+        elements.sort_by_key(|element| element.start);
+        for element in elements.into_iter().rev() {
+            if let Some(replacement) = rewrite(&self.text(element.clone())) {
+                self.splice(element, &replacement);
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts a new `key=value` line below the last top-level assignment to `anchor`. Returns
+    /// `false` if there is no such assignment.
+    pub fn insert_after(&mut self, anchor: &str, key: &str, value: &str) -> Result<bool> {
+        let parsed = parser::parse(&self.content)?;
+        let end = match parsed
+            .assignments()
+            .iter()
+            .rev()
+            .find(|assignment| assignment.name == anchor)
+        {
+            Some(assignment) => assignment.value.end,
+            None => return Ok(false),
+        };
+        let mut line_end = self.line_end(end);
+        if self.content[..line_end].ends_with(b"\r") {
+            line_end -= 1;
+        }
+        let line = format!("{}{}={}", self.newline(), key, value);
+        self.splice(line_end..line_end, &line);
+        Ok(true)
+    }
+}
+
+/// Explicit array layouts, overriding the one of the array being replaced.
+#[derive(Debug, Clone, Copy)]
+pub enum Layout {
+    /// All elements on the line of the assignment.
+    Inline,
+    /// One element per line, aligned with the first one, or with `indent` on lines of their own
+    /// between the parentheses.
+    PerLine { indent: Option<usize> },
+}
+
+/// How an existing array is written: the quote around its elements and the whitespace after
+/// `(`, between elements and before `)`.
+struct ArrayStyle {
+    quote: &'static str,
+    open: String,
+    separator: String,
+    close: String,
+}
+
+impl ArrayStyle {
+    /// One quoted element per line, aligned with the first one at `column`.
+    fn new(column: usize, newline: &str) -> ArrayStyle {
+        ArrayStyle {
+            quote: "'",
+            open: String::new(),
+            separator: format!("{}{}", newline, " ".repeat(column)),
+            close: String::new(),
+        }
+    }
+
+    fn detect(
+        content: &[u8],
+        assignment: &parser::Assignment,
+        newline: &str,
+        layout: Option<Layout>,
+    ) -> ArrayStyle {
+        let line_start = content[..assignment.value.start]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let line = String::from_utf8_lossy(&content[line_start..assignment.value.start]);
+        let column = line.chars().count() + 1;
+        let mut style = ArrayStyle::detect_layout(content, assignment, column, newline);
+        if let Some(layout) = layout {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            style.apply(layout, column, indent, newline);
+        }
+        style
+    }
+
+    fn detect_layout(
+        content: &[u8],
+        assignment: &parser::Assignment,
+        column: usize,
+        newline: &str,
+    ) -> ArrayStyle {
+        let mut style = ArrayStyle::new(column, newline);
+        let elements = &assignment.elements;
+        let (first, last) = match (elements.first(), elements.last()) {
+            (Some(first), Some(last)) if content[assignment.value.clone()].starts_with(b"(") => {
+                (first, last)
+            }
+            _ => return style,
+        };
+        let whitespace = |range: Range<usize>| {
+            let text = String::from_utf8_lossy(&content[range]);
+            if text.trim().is_empty() {
+                Some(text.into_owned())
+            } else {
+                None
+            }
+        };
+
+        style.quote = match content[first.start] {
+            b'"' => "\"",
+            b'\'' => "'",
+            _ => "",
+        };
+        style.open = whitespace(assignment.value.start + 1..first.start).unwrap_or_default();
+        style.close = whitespace(last.end..assignment.value.end - 1).unwrap_or_default();
+        if let Some(separator) = elements
+            .get(1)
+            .and_then(|second| whitespace(first.end..second.start))
+        {
+            style.separator = separator;
+        } else if style.open.contains('\n') {
+            style.separator = style.open.clone();
+        }
+        style
+    }
+
+    /// Lays the array out explicitly. `column` is the one just after `(` and `indent` the
+    /// whitespace the assignment's line starts with.
+    fn apply(&mut self, layout: Layout, column: usize, indent: &str, newline: &str) {
+        match layout {
+            Layout::Inline => {
+                self.open = String::new();
+                self.separator = " ".to_string();
+                self.close = String::new();
+            }
+            Layout::PerLine { indent: None } => {
+                self.open = String::new();
+                self.separator = format!("{}{}", newline, " ".repeat(column));
+                self.close = String::new();
+            }
+            Layout::PerLine {
+                indent: Some(width),
+            } => {
+                self.open = format!("{}{}{}", newline, indent, " ".repeat(width));
+                self.separator = self.open.clone();
+                self.close = format!("{}{}", newline, indent);
+            }
+        }
+    }
+
+    fn format(&self, values: &[String]) -> String {
+        let elements: Vec<String> = values
+            .iter()
+            .map(|value| format!("{}{}{}", self.quote, value, self.quote))
+            .collect();
+        format!(
+            "({}{}{})",
+            self.open,
+            elements.join(&self.separator),
+            self.close
+        )
+    }
+}
+
+impl AsRef<[u8]> for Pkgbuild {
+    fn as_ref(&self) -> &[u8] {
+        &self.content
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pkgbuild(content: &[u8]) -> Pkgbuild {
+        Pkgbuild {
+            content: content.to_vec(),
+        }
+    }
+
+    #[test]
+    fn set_keeps_crlf() {
+        let mut pkgbuild = pkgbuild(b"# \xe9t\xe9\r\npkgver=1.0\r\npkgrel=3\r\n");
+        assert!(pkgbuild.set("pkgver", "2.0").unwrap());
+        assert_eq!(
+            pkgbuild.content,
+            b"# \xe9t\xe9\r\npkgver=2.0\r\npkgrel=3\r\n"
+        );
+    }
+
+    #[test]
+    fn set_keeps_missing_final_newline() {
+        let mut pkgbuild = pkgbuild(b"pkgver=1.0\npkgrel=3");
+        assert!(pkgbuild.set("pkgrel", "1").unwrap());
+        assert_eq!(pkgbuild.content, b"pkgver=1.0\npkgrel=1");
+    }
+
+    #[test]
+    fn set_keeps_non_utf8() {
+        let mut pkgbuild = pkgbuild(b"# Maintainer: J\xf6rg\npkgver=1.0 # \xff\xfe\n");
+        assert!(pkgbuild.set("pkgver", "2.0").unwrap());
+        assert_eq!(
+            pkgbuild.content,
+            b"# Maintainer: J\xf6rg\npkgver=2.0 # \xff\xfe\n"
+        );
+    }
+
+    #[test]
+    fn set_array_keeps_crlf() {
+        let mut pkgbuild = pkgbuild(b"sha256sums=('a'\r\n            'b')\r\n# \xe9\r\n");
+        let values = ["c".to_string(), "d".to_string()];
+        assert!(pkgbuild.set_array("sha256sums", &values, None).unwrap());
+        assert_eq!(
+            pkgbuild.content,
+            b"sha256sums=('c'\r\n            'd')\r\n# \xe9\r\n"
+        );
+    }
+
+    #[test]
+    fn set_array_lays_out_with_crlf() {
+        let mut pkgbuild = pkgbuild(b"# \xe9\r\nsha256sums=('a' 'b')\r\npkgrel=1");
+        let values = ["c".to_string(), "d".to_string()];
+        let layout = Layout::PerLine { indent: Some(2) };
+        assert!(pkgbuild
+            .set_array("sha256sums", &values, Some(layout))
+            .unwrap());
+        assert_eq!(
+            pkgbuild.content,
+            b"# \xe9\r\nsha256sums=(\r\n  'c'\r\n  'd'\r\n)\r\npkgrel=1"
+        );
+    }
+
+    #[test]
+    fn set_array_keeps_missing_final_newline() {
+        let mut pkgbuild = pkgbuild(b"pkgver=1.0\nmd5sums=('a')");
+        assert!(pkgbuild
+            .set_array("md5sums", &["b".to_string()], Some(Layout::Inline))
+            .unwrap());
+        assert_eq!(pkgbuild.content, b"pkgver=1.0\nmd5sums=('b')");
+    }
+
+    #[test]
+    fn remove_keeps_crlf() {
+        let mut pkgbuild = pkgbuild(b"# \xe9\r\nmd5sums=('a')\r\npkgrel=1\r\n");
+        assert!(pkgbuild.remove("md5sums").unwrap());
+        assert_eq!(pkgbuild.content, b"# \xe9\r\npkgrel=1\r\n");
+    }
+
+    #[test]
+    fn remove_last_line_keeps_missing_final_newline() {
+        let mut pkgbuild = pkgbuild(b"pkgrel=1\r\n# \xe9\r\nmd5sums=('a')");
+        assert!(pkgbuild.remove("md5sums").unwrap());
+        assert_eq!(pkgbuild.content, b"pkgrel=1\r\n# \xe9");
+    }
+
+    #[test]
+    fn remove_keeps_non_utf8_on_the_line() {
+        let mut pkgbuild = pkgbuild(b"pkgrel=1\nmd5sums=('a') # \xff\n");
+        assert!(pkgbuild.remove("md5sums").unwrap());
+        assert_eq!(pkgbuild.content, b"pkgrel=1\n # \xff\n");
+    }
+}
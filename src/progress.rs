@@ -0,0 +1,115 @@
+//! Download progress: a bar per source with speed and ETA on a terminal, otherwise a log line
+//! every few seconds.
+
+use crate::logging;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where the progress of all downloads goes, stderr so it stays out of the PKGBUILD on stdout.
+pub struct Progress {
+    bars: Option<MultiProgress>,
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        // Quiet runs show neither bars nor progress messages.
+        let bars = if std::io::stderr().is_terminal() && tracing::enabled!(tracing::Level::INFO) {
+            Some(MultiProgress::new())
+        } else {
+            None
+        };
+        logging::set_bars(bars.clone());
+        Progress { bars }
+    }
+
+    /// Starts tracking a download of `total` bytes, if known.
+    pub fn start(&self, name: &str, total: Option<u64>) -> Download {
+        match &self.bars {
+            Some(bars) => {
+                let bar = bars.add(match total {
+                    Some(total) => ProgressBar::new(total).with_style(
+                        ProgressStyle::with_template(
+                            "{prefix} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}",
+                        )
+                        .unwrap()
+                        .progress_chars("=> "),
+                    ),
+                    None => ProgressBar::new_spinner().with_style(
+                        ProgressStyle::with_template("{prefix} {spinner} {bytes} {bytes_per_sec}")
+                            .unwrap(),
+                    ),
+                });
+                bar.set_prefix(name.to_string());
+                Download::Bar(bar)
+            }
+            None => {
+                let now = Instant::now();
+                Download::Log {
+                    name: name.to_string(),
+                    total,
+                    done: 0,
+                    started: now,
+                    logged: now,
+                }
+            }
+        }
+    }
+}
+
+pub enum Download {
+    Bar(ProgressBar),
+    Log {
+        name: String,
+        total: Option<u64>,
+        done: u64,
+        started: Instant,
+        logged: Instant,
+    },
+}
+
+impl Download {
+    pub fn advance(&mut self, len: usize) {
+        match self {
+            Download::Bar(bar) => bar.inc(len as u64),
+            Download::Log {
+                name,
+                total,
+                done,
+                started,
+                logged,
+            } => {
+                *done += len as u64;
+                if logged.elapsed() < LOG_INTERVAL {
+                    return;
+                }
+                *logged = Instant::now();
+                let speed = *done as f64 / started.elapsed().as_secs_f64();
+                match total {
+                    Some(total) => info!(
+                        "{}: {} of {} ({}/s)",
+                        name,
+                        HumanBytes(*done),
+                        HumanBytes(*total),
+                        HumanBytes(speed as u64)
+                    ),
+                    None => info!(
+                        "{}: {} ({}/s)",
+                        name,
+                        HumanBytes(*done),
+                        HumanBytes(speed as u64)
+                    ),
+                }
+            }
+        }
+    }
+
+    pub fn finish(self) {
+        if let Download::Bar(bar) = self {
+            bar.finish();
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! The JSON report of `--output json`, for CI bots and dashboards to consume the outcome of a
+//! bump without scraping the messages, which go to stderr instead.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Error, Result, Write};
+use std::os::unix::io::FromRawFd;
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub pkgbase: String,
+    pub pkgnames: Vec<String>,
+    /// Full versions with the pkgrel, e.g. `1:2.0.1-1`, the old one `None` without a pkgver.
+    pub old_version: Option<String>,
+    pub new_version: String,
+    pub sources: Vec<SourceReport>,
+    /// Seconds spent downloading and hashing the sources.
+    pub download_seconds: f64,
+    /// Seconds the whole bump took.
+    pub seconds: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceReport {
+    /// The architecture of `source_<arch>` entries.
+    pub arch: Option<String>,
+    pub url: String,
+    pub filename: String,
+    /// Bytes of the downloaded file, `None` if it is not on disk, like VCS sources.
+    pub size: Option<u64>,
+    /// The sums before and after, by algorithm.
+    pub old_sums: BTreeMap<String, String>,
+    pub new_sums: BTreeMap<String, String>,
+}
+
+/// Makes everything printed to stdout go to stderr, and returns the original stdout to write the
+/// report to.
+pub fn redirect_stdout() -> Result<File> {
+    std::io::stdout().flush()?;
+    // SAFETY: only duplicates the standard descriptors, and the duplicate of stdout is owned by
+    // the returned file alone.
+    unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        let report = File::from_raw_fd(fd);
+        if libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(report)
+    }
+}
+
+impl Report {
+    pub fn write(&self, mut out: File) -> Result<()> {
+        serde_json::to_writer_pretty(&mut out, self)?;
+        writeln!(out)
+    }
+}
@@ -0,0 +1,124 @@
+//! minisign and signify signatures: Ed25519 over the file, or over its BLAKE2b-512 hash for
+//! the prehashed signatures of recent minisign, checked against configured public keys.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use blake2::Blake2b;
+use digest::Digest;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+const UNTRUSTED_COMMENT: &str = "untrusted comment:";
+const TRUSTED_COMMENT: &str = "trusted comment: ";
+
+struct PublicKey {
+    id: [u8; 8],
+    key: Vec<u8>,
+}
+
+/// Whether a detached signature is in the format of minisign or signify rather than OpenPGP,
+/// which also uses `.sig`.
+pub fn is_signify(signature: &[u8]) -> bool {
+    signature.starts_with(UNTRUSTED_COMMENT.as_bytes())
+}
+
+/// Verifies the minisign or signify `signature` of `file` against `keys`, each the base64 line
+/// of a public key, and returns the ID of the key that made it.
+pub fn verify(signature: &Path, file: &Path, keys: &[String]) -> Result<String> {
+    let keys = keys
+        .iter()
+        .map(|key| parse_key(key))
+        .collect::<Result<Vec<_>>>()?;
+    let signature = std::fs::read_to_string(signature)?;
+    let mut lines = signature.lines();
+    if !lines
+        .next()
+        .unwrap_or_default()
+        .starts_with(UNTRUSTED_COMMENT)
+    {
+        return Err(invalid("missing the untrusted comment"));
+    }
+    let blob = decode(lines.next().unwrap_or_default())?;
+    if blob.len() != 74 {
+        return Err(invalid("malformed signature"));
+    }
+    let (algorithm, rest) = blob.split_at(2);
+    let (id, sig) = rest.split_at(8);
+    let key = keys.iter().find(|key| key.id == id).ok_or_else(|| {
+        Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "made by key {}, which is not a configured signing key",
+                key_id(id)
+            ),
+        )
+    })?;
+    let content = std::fs::read(file)?;
+    let message = match algorithm {
+        b"Ed" => content,
+        b"ED" => Blake2b::digest(&content).to_vec(),
+        _ => return Err(invalid("unsupported signature algorithm")),
+    };
+    check(key, &message, sig)?;
+    // minisign also signs its trusted comment, together with the signature.
+    if let Some(comment) = lines.next() {
+        let comment = comment
+            .strip_prefix(TRUSTED_COMMENT)
+            .ok_or_else(|| invalid("malformed trusted comment"))?;
+        let global = decode(lines.next().unwrap_or_default())?;
+        check(key, &[sig, comment.as_bytes()].concat(), &global)?;
+    }
+    Ok(key_id(&key.id))
+}
+
+/// Parses a public key, with the untrusted comment line of a key file or without it.
+fn parse_key(key: &str) -> Result<PublicKey> {
+    let line = key
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty() && !line.starts_with(UNTRUSTED_COMMENT))
+        .unwrap_or_default();
+    let blob = decode(line)?;
+    if blob.len() != 42 || &blob[..2] != b"Ed" {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("`{}` is not a minisign or signify public key", key),
+        ));
+    }
+    let mut id = [0; 8];
+    id.copy_from_slice(&blob[2..10]);
+    Ok(PublicKey {
+        id,
+        key: blob[10..].to_vec(),
+    })
+}
+
+fn check(key: &PublicKey, message: &[u8], signature: &[u8]) -> Result<()> {
+    UnparsedPublicKey::new(&ED25519, &key.key)
+        .verify(message, signature)
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("bad signature by key {}", key_id(&key.id)),
+            )
+        })
+}
+
+/// A key ID the way minisign shows it, as the little-endian number it stores.
+fn key_id(id: &[u8]) -> String {
+    id.iter()
+        .rev()
+        .map(|byte| format!("{:02X}", byte))
+        .collect()
+}
+
+fn decode(line: &str) -> Result<Vec<u8>> {
+    STANDARD
+        .decode(line.trim())
+        .map_err(|e| invalid(e.to_string()))
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
@@ -0,0 +1,138 @@
+//! How a new source archive differs from the previous version's: the files added, removed and
+//! modified, and which of them are new executables or binaries, to review before committing.
+
+use crate::archive;
+use digest::Digest;
+use indicatif::HumanBytes;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::io::Result;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Bytes looked at to tell a binary file, which has a NUL among them, like git does.
+const BINARY_PROBE: usize = 8000;
+
+struct Entry {
+    size: u64,
+    executable: bool,
+    binary: bool,
+    sum: Vec<u8>,
+}
+
+/// Prints a summary of the differences between the archives `old` and `new`.
+pub fn print(old: &Path, old_name: &str, new: &Path, new_name: &str) -> Result<()> {
+    let old = list(old, old_name)?;
+    let new = list(new, new_name)?;
+    let mut lines = Vec::new();
+    let (mut added, mut removed, mut modified) = (0, 0, 0);
+    for (path, entry) in &new {
+        match old.get(path) {
+            None => {
+                added += 1;
+                lines.push(format!("added {}{}", path.display(), flags(entry, None)));
+            }
+            Some(previous)
+                if previous.sum != entry.sum || previous.executable != entry.executable =>
+            {
+                modified += 1;
+                lines.push(format!(
+                    "modified {}{}",
+                    path.display(),
+                    flags(entry, Some(previous))
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+    for path in old.keys().filter(|path| !new.contains_key(*path)) {
+        removed += 1;
+        lines.push(format!("removed {}", path.display()));
+    }
+    let size = |entries: &BTreeMap<PathBuf, Entry>| entries.values().map(|entry| entry.size).sum();
+    let (old_size, new_size): (u64, u64) = (size(&old), size(&new));
+    let delta = if new_size >= old_size {
+        format!("+{}", HumanBytes(new_size - old_size))
+    } else {
+        format!("-{}", HumanBytes(old_size - new_size))
+    };
+    println!(
+        "{} -> {}: {} added, {} removed, {} modified, {} ({} -> {})",
+        old_name,
+        new_name,
+        added,
+        removed,
+        modified,
+        delta,
+        HumanBytes(old_size),
+        HumanBytes(new_size)
+    );
+    for line in lines {
+        println!("    {}", line);
+    }
+    Ok(())
+}
+
+/// What sets `entry` apart for a reviewer: being executable or binary, where `previous` was not.
+fn flags(entry: &Entry, previous: Option<&Entry>) -> String {
+    let mut flags = Vec::new();
+    if entry.executable && !previous.is_some_and(|previous| previous.executable) {
+        flags.push("executable");
+    }
+    if entry.binary && !previous.is_some_and(|previous| previous.binary) {
+        flags.push("binary");
+    }
+    match (flags.is_empty(), previous) {
+        (true, _) => String::new(),
+        (false, None) => format!(" ({})", flags.join(", ")),
+        (false, Some(_)) => format!(" (now {})", flags.join(", ")),
+    }
+}
+
+/// The files in the archive at `path`, relative to its top directory when it has a single one,
+/// since that usually has the version in its name.
+fn list(path: &Path, filename: &str) -> Result<BTreeMap<PathBuf, Entry>> {
+    let dir = tempfile::tempdir()?;
+    archive::extract(path, filename, dir.path())?;
+    let mut root = dir.path().to_path_buf();
+    let top: Vec<_> = std::fs::read_dir(&root)?.collect::<Result<_>>()?;
+    if let [top] = top.as_slice() {
+        if top.file_type()?.is_dir() {
+            root = top.path();
+        }
+    }
+    let mut entries = BTreeMap::new();
+    walk(&root, &root, &mut entries)?;
+    Ok(entries)
+}
+
+fn walk(root: &Path, dir: &Path, entries: &mut BTreeMap<PathBuf, Entry>) -> Result<()> {
+    for child in std::fs::read_dir(dir)? {
+        let child = child?;
+        let path = child.path();
+        let metadata = std::fs::symlink_metadata(&path)?;
+        let entry = if metadata.is_dir() {
+            walk(root, &path, entries)?;
+            continue;
+        } else if metadata.file_type().is_symlink() {
+            let target = std::fs::read_link(&path)?;
+            Entry {
+                size: 0,
+                executable: false,
+                binary: false,
+                sum: target.to_string_lossy().as_bytes().to_vec(),
+            }
+        } else {
+            let content = std::fs::read(&path)?;
+            Entry {
+                size: metadata.len(),
+                executable: metadata.permissions().mode() & 0o111 != 0,
+                binary: content[..content.len().min(BINARY_PROBE)].contains(&0),
+                sum: Sha256::digest(&content).to_vec(),
+            }
+        };
+        let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        entries.insert(relative, entry);
+    }
+    Ok(())
+}
@@ -0,0 +1,234 @@
+//! The `.SRCINFO` the AUR requires next to a PKGBUILD, as `makepkg --printsrcinfo` prints it,
+//! or generated natively the same way where makepkg is not installed.
+
+use crate::{logging, parser};
+use std::fs::Permissions;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+const SRCINFO: &str = ".SRCINFO";
+/// makepkg's `known_hash_algos`, which unlike pkgbump's do not include the SHA-3 ones.
+const HASH_ALGOS: &[&str] = &[
+    "ck", "md5", "sha1", "sha224", "sha256", "sha384", "sha512", "b2",
+];
+
+/// The attributes of each section, in the order makepkg writes them. Global and architecture
+/// arrays are followed by the sums arrays, and every section ends with the architecture arrays
+/// of each of its architectures but `any`.
+const GLOBAL_SCALARS: &[&str] = &[
+    "pkgdesc",
+    "pkgver",
+    "pkgrel",
+    "epoch",
+    "url",
+    "install",
+    "changelog",
+];
+const GLOBAL_ARRAYS: &[&str] = &[
+    "arch",
+    "groups",
+    "license",
+    "checkdepends",
+    "makedepends",
+    "depends",
+    "optdepends",
+    "provides",
+    "conflicts",
+    "replaces",
+    "noextract",
+    "options",
+    "backup",
+    "source",
+    "validpgpkeys",
+];
+const PACKAGE_SCALARS: &[&str] = &["pkgdesc", "url", "install", "changelog"];
+const PACKAGE_ARRAYS: &[&str] = &[
+    "arch",
+    "groups",
+    "license",
+    "checkdepends",
+    "depends",
+    "optdepends",
+    "provides",
+    "conflicts",
+    "replaces",
+    "options",
+    "backup",
+];
+const ARCH_ARRAYS: &[&str] = &[
+    "source",
+    "provides",
+    "conflicts",
+    "depends",
+    "replaces",
+    "optdepends",
+    "makedepends",
+    "checkdepends",
+];
+
+/// Runs `makepkg --printsrcinfo` on `content`, or returns `None` if makepkg is not installed.
+pub fn printsrcinfo(content: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut buildfile = NamedTempFile::new()?;
+    buildfile.write_all(content)?;
+    let output = match logging::command(
+        Command::new("makepkg")
+            .arg("--printsrcinfo")
+            .arg("-p")
+            .arg(buildfile.path()),
+    )
+    .output()
+    {
+        Ok(output) => output,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if !output.status.success() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "makepkg --printsrcinfo failed on the edited PKGBUILD: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(Some(output.stdout))
+}
+
+/// The `.SRCINFO` of the PKGBUILD `content`, byte for byte what `makepkg --printsrcinfo`
+/// prints, from the native parser. Split packages' sections have what their `package_*`
+/// functions assign.
+pub fn generate(content: &[u8]) -> Result<Vec<u8>> {
+    let parsed = parser::parse(content)?;
+    let sums: Vec<String> = HASH_ALGOS
+        .iter()
+        .map(|algo| format!("{}sums", algo))
+        .collect();
+    let arrays = |names: &'static [&'static str]| {
+        names
+            .iter()
+            .map(|name| name.to_string())
+            .chain(sums.clone())
+            .collect::<Vec<_>>()
+    };
+    let mut srcinfo = Vec::new();
+    let pkgnames = parsed.array("pkgname")?;
+    let pkgbase = match parsed
+        .scalar("pkgbase")?
+        .filter(|pkgbase| !pkgbase.is_empty())
+    {
+        Some(pkgbase) => pkgbase,
+        None => pkgnames.first().cloned().unwrap_or_default(),
+    };
+    writeln!(srcinfo, "pkgbase = {}", pkgbase)?;
+    // Globals are left out when empty, like makepkg does.
+    for name in GLOBAL_SCALARS {
+        if let Some(value) = parsed.scalar(name)?.filter(|value| !value.is_empty()) {
+            attribute(&mut srcinfo, name, &[value])?;
+        }
+    }
+    let arches = parsed.array("arch")?;
+    let arch_names = |arches: &[String]| {
+        arches
+            .iter()
+            .filter(|arch| *arch != "any")
+            .flat_map(|arch| {
+                arrays(ARCH_ARRAYS)
+                    .into_iter()
+                    .map(move |name| format!("{}_{}", name, arch))
+            })
+            .collect::<Vec<_>>()
+    };
+    for name in arrays(GLOBAL_ARRAYS).into_iter().chain(arch_names(&arches)) {
+        let values = parsed.array(&name)?;
+        if !values.is_empty() {
+            attribute(&mut srcinfo, &name, &values)?;
+        }
+    }
+    writeln!(srcinfo)?;
+    for pkgname in &pkgnames {
+        writeln!(srcinfo, "pkgname = {}", pkgname)?;
+        let function = format!("package_{}", pkgname);
+        // Packages only have what their function overrides, even if it is empty.
+        let mut overridden = |name: &str, scalar: bool| -> Result<()> {
+            if let Some(value) = parsed.function_value(&function, name)? {
+                let values = match value.values() {
+                    [first, ..] if scalar => std::slice::from_ref(first),
+                    values => values,
+                };
+                attribute(&mut srcinfo, name, values)?;
+            }
+            Ok(())
+        };
+        for name in PACKAGE_SCALARS {
+            overridden(name, true)?;
+        }
+        for name in PACKAGE_ARRAYS {
+            overridden(name, false)?;
+        }
+        let arches = match parsed.function_value(&function, "arch")? {
+            Some(arches) => arches.values().to_vec(),
+            None => arches.clone(),
+        };
+        for name in arch_names(&arches) {
+            overridden(&name, false)?;
+        }
+        writeln!(srcinfo)?;
+    }
+    Ok(srcinfo)
+}
+
+/// Writes `name = value` lines for `values`, with runs of whitespace in them squeezed into a
+/// single space and trimmed like makepkg does, or a line without a value if there are none.
+fn attribute(srcinfo: &mut Vec<u8>, name: &str, values: &[String]) -> Result<()> {
+    if values.is_empty() {
+        return writeln!(srcinfo, "\t{} = ", name);
+    }
+    for value in values {
+        let value: Vec<_> = value
+            .split(|c| " \t\n\r\x0b\x0c".contains(c))
+            .filter(|word| !word.is_empty())
+            .collect();
+        writeln!(srcinfo, "\t{} = {}", name, value.join(" "))?;
+    }
+    Ok(())
+}
+
+/// Replaces `.SRCINFO` with that of the PKGBUILD `content`, through a temporary file like the
+/// PKGBUILD and keeping its permissions, and stages it when the package directory is a git work tree, because the AUR refuses
+/// pushes of a PKGBUILD without its `.SRCINFO`. Without makepkg, it is generated natively.
+pub fn update(content: &[u8]) -> Result<()> {
+    let srcinfo = match printsrcinfo(content)? {
+        Some(srcinfo) => srcinfo,
+        None => generate(content)?,
+    };
+    let mut file = tempfile::Builder::new()
+        // Masked by the umask, like any new file, rather than the 0600 of temporary files.
+        .permissions(Permissions::from_mode(0o666))
+        .tempfile_in(".")?;
+    file.write_all(&srcinfo)?;
+    if let Ok(metadata) = std::fs::metadata(SRCINFO) {
+        file.as_file().set_permissions(metadata.permissions())?;
+    }
+    file.as_file().sync_all()?;
+    file.persist(SRCINFO).map_err(|e| e.error)?;
+    if is_work_tree() {
+        let output = logging::command(Command::new("git").args(["add", "--", SRCINFO])).output()?;
+        if !output.status.success() {
+            return Err(Error::other(format!(
+                "git add {} failed: {}",
+                SRCINFO,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Whether the current directory is in a git work tree, `false` without git.
+fn is_work_tree() -> bool {
+    logging::command(Command::new("git").args(["rev-parse", "--is-inside-work-tree"]))
+        .output()
+        .is_ok_and(|output| output.status.success() && output.stdout.starts_with(b"true"))
+}
@@ -0,0 +1,98 @@
+//! Checks an edited PKGBUILD before it is used: it must still be valid bash, and both the native
+//! parser and `makepkg --printsrcinfo` (when available) must see the values pkgbump meant to set.
+
+use crate::{logging, parser, srcinfo};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::process::{Command, Stdio};
+
+/// Validates `content` against the `expected` variable values.
+pub fn validate(content: &[u8], expected: &[(String, Vec<String>)]) -> Result<()> {
+    check_syntax(content)?;
+
+    let parsed = parser::parse(content)?;
+    for (key, values) in expected {
+        compare("the edited PKGBUILD", key, &parsed.elements(key)?, values)?;
+    }
+
+    let srcinfo = match printsrcinfo(content)? {
+        Some(srcinfo) => srcinfo,
+        None => return Ok(()),
+    };
+    for (key, values) in expected {
+        // makepkg only sees the branches that run, and leaves out architecture-specific
+        // variables of architectures not in `arch`.
+        if parsed.segments(key)?.len() > 1 {
+            continue;
+        }
+        if let Some(actual) = srcinfo.get(key.as_str()) {
+            compare("makepkg --printsrcinfo", key, actual, values)?;
+        } else if key == "pkgver" || key == "pkgrel" {
+            compare("makepkg --printsrcinfo", key, &[], values)?;
+        }
+    }
+    Ok(())
+}
+
+fn compare(what: &str, key: &str, actual: &[String], expected: &[String]) -> Result<()> {
+    if actual == expected {
+        return Ok(());
+    }
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "{} has {}=({}) instead of ({})",
+            what,
+            key,
+            actual.join(" "),
+            expected.join(" ")
+        ),
+    ))
+}
+
+fn check_syntax(content: &[u8]) -> Result<()> {
+    let mut child = logging::command(
+        Command::new("bash")
+            .arg("-n")
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )
+    .spawn()?;
+    child.stdin.take().unwrap().write_all(content)?;
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "the edited PKGBUILD is not valid bash: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ))
+    }
+}
+
+/// Runs `makepkg --printsrcinfo` on `content` and returns the pkgbase section, or `None` if
+/// makepkg is not installed.
+fn printsrcinfo(content: &[u8]) -> Result<Option<HashMap<String, Vec<String>>>> {
+    let output = match srcinfo::printsrcinfo(content)? {
+        Some(output) => output,
+        None => return Ok(None),
+    };
+    let mut srcinfo: HashMap<String, Vec<String>> = HashMap::new();
+    for line in String::from_utf8_lossy(&output).lines() {
+        let (key, value) = match line.trim().split_once(" = ") {
+            Some(pair) => pair,
+            None => continue,
+        };
+        if key == "pkgname" {
+            break;
+        }
+        srcinfo
+            .entry(key.to_string())
+            .or_default()
+            .push(value.to_string());
+    }
+    Ok(Some(srcinfo))
+}
@@ -0,0 +1,151 @@
+//! Version control sources, e.g. `git+https://example.com/repo.git#tag=v1.0`.
+
+use crate::logging;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::process::Command;
+
+pub const PROTOCOLS: &[&str] = &["bzr", "fossil", "git", "hg", "svn"];
+
+/// Fragments that pin a source to something usually named after the version.
+const VERSIONED_FRAGMENTS: &[&str] = &["tag", "branch"];
+
+/// Returns the byte range of the value of a `#tag=`/`#branch=` fragment in a raw source
+/// entry, which may still carry its shell quotes.
+fn fragment_value(entry: &str) -> Option<(usize, usize)> {
+    let (_, rest) = entry.split_once('#')?;
+    let fragment = entry.len() - rest.len();
+    let (kind, _) = rest.split_once('=')?;
+    if !VERSIONED_FRAGMENTS.contains(&kind) {
+        return None;
+    }
+    let start = fragment + kind.len() + 1;
+    let end = entry[start..]
+        .find(['\'', '"'])
+        .map_or(entry.len(), |i| start + i);
+    Some((start, end))
+}
+
+/// Replaces a hardcoded `old` version in the fragment of a raw source entry with `new`.
+pub fn bump_fragment(entry: &str, old: &str, new: &str) -> Option<String> {
+    let (start, end) = fragment_value(entry)?;
+    let value = &entry[start..end];
+    if old.is_empty() || old == new || !value.contains(old) {
+        return None;
+    }
+    Some(format!(
+        "{}{}{}",
+        &entry[..start],
+        value.replace(old, new),
+        &entry[end..]
+    ))
+}
+
+/// Returns the repository of a git source URL pinned with `#commit=`.
+pub fn pinned_repository(url: &str) -> Option<&str> {
+    let (repository, fragment) = url.strip_prefix("git+")?.split_once('#')?;
+    if fragment.starts_with("commit=") {
+        Some(repository.split('?').next().unwrap())
+    } else {
+        None
+    }
+}
+
+/// Looks up the first of `tags` that exists in `repository` and returns it with the commit it
+/// points to, peeling annotated tags.
+pub fn resolve_tag(repository: &str, tags: &[String]) -> Result<(String, String)> {
+    let output =
+        logging::command(Command::new("git").args(["ls-remote", "--tags", repository])).output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "git ls-remote {} failed: {}",
+            repository,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let refs: HashMap<&str, &str> = stdout
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(commit, name)| (name, commit))
+        .collect();
+    for tag in tags {
+        let peeled = format!("refs/tags/{}^{{}}", tag);
+        let name = format!("refs/tags/{}", tag);
+        if let Some(commit) = refs
+            .get(peeled.as_str())
+            .or_else(|| refs.get(name.as_str()))
+        {
+            return Ok((tag.clone(), commit.to_string()));
+        }
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        format!(
+            "none of the tags {} exist in {}",
+            tags.join(", "),
+            repository
+        ),
+    ))
+}
+
+/// Clones or updates the repository of a VCS source URL into `dest` and checks out the ref
+/// selected by its fragment, the way makepkg extracts git sources.
+pub fn checkout(url: &str, dest: &Path) -> Result<()> {
+    let repository = match url.strip_prefix("git+") {
+        Some(repository) => repository,
+        None if url.starts_with("git://") => url,
+        None => {
+            return Err(Error::other(format!(
+                "checking out {} is not supported, only git is",
+                url
+            )))
+        }
+    };
+    let (repository, fragment) = repository.split_once('#').unwrap_or((repository, ""));
+    let repository = repository.split('?').next().unwrap();
+    if dest.join(".git").exists() {
+        git(&[
+            "-C",
+            &dest.to_string_lossy(),
+            "fetch",
+            "--tags",
+            "--force",
+            "origin",
+        ])?;
+    } else {
+        git(&["clone", "--quiet", repository, &dest.to_string_lossy()])?;
+    }
+    let reference = match fragment.split_once('=') {
+        Some(("commit", commit)) => commit.to_string(),
+        Some(("tag", tag)) => format!("tags/{}", tag),
+        Some(("branch", branch)) => format!("origin/{}", branch),
+        _ => "origin/HEAD".to_string(),
+    };
+    git(&[
+        "-C",
+        &dest.to_string_lossy(),
+        "checkout",
+        "--quiet",
+        "--force",
+        "--no-track",
+        "-B",
+        "makepkg",
+        &reference,
+        "--",
+    ])
+}
+
+fn git(args: &[&str]) -> Result<()> {
+    let output = logging::command(Command::new("git").args(args)).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
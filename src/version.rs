@@ -0,0 +1,198 @@
+//! Version comparison following pacman's `vercmp` semantics.
+
+use std::cmp::Ordering;
+use std::io::{Error, ErrorKind, Result};
+
+/// Turns an upstream version like `v1.2-rc1` into a valid pkgver like `1.2rc1`: a leading `v`
+/// is dropped, dashes before pre-release tags are removed so they sort before the release, and
+/// any other dash becomes an underscore.
+pub fn normalize(version: &str) -> String {
+    let version = version.trim();
+    let version = match version.strip_prefix(|c| c == 'v' || c == 'V') {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => rest,
+        _ => version,
+    };
+    let mut normalized = String::with_capacity(version.len());
+    let mut chars = version.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '-' if chars.peek().is_some_and(char::is_ascii_alphabetic) => {}
+            '-' => normalized.push('_'),
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// Replaces each occurrence of the version `old` in `text` with `new`, unless it is part of a
+/// longer version, as in `11.2` or `1.2.3` for `1.2`. Returns `None` if there is none.
+pub fn replace_version(text: &str, old: &str, new: &str) -> Option<String> {
+    if old.is_empty() || old == new {
+        return None;
+    }
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = 0;
+    for (start, _) in text.match_indices(old) {
+        let end = start + old.len();
+        let before = text[..start].chars().next_back();
+        let mut after = text[end..].chars();
+        let continues = match after.next() {
+            Some(c) if c.is_ascii_alphanumeric() => true,
+            Some('.') | Some('_') => after.next().is_some_and(|c| c.is_ascii_digit()),
+            _ => false,
+        };
+        if before.is_some_and(|c| c.is_ascii_alphanumeric() || c == '.') || continues {
+            continue;
+        }
+        replaced.push_str(&text[rest..start]);
+        replaced.push_str(new);
+        rest = end;
+    }
+    if rest == 0 {
+        return None;
+    }
+    replaced.push_str(&text[rest..]);
+    Some(replaced)
+}
+
+/// Rejects versions makepkg does not accept as pkgver.
+pub fn check_pkgver(pkgver: &str) -> Result<()> {
+    if pkgver.is_empty()
+        || pkgver.contains(|c: char| c == ':' || c == '/' || c == '-' || c.is_whitespace())
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "invalid pkgver `{}`, it may not be empty or contain ':', '/', '-' or whitespace",
+                pkgver
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Joins an epoch and a pkgver the way pacman displays them, omitting a zero epoch.
+pub fn full_version(epoch: Option<&str>, pkgver: &str) -> String {
+    match epoch {
+        Some(epoch) if !epoch.is_empty() && epoch != "0" => format!("{}:{}", epoch, pkgver),
+        _ => pkgver.to_string(),
+    }
+}
+
+/// Compares two `[epoch:]pkgver[-pkgrel]` strings like `alpm_pkg_vercmp`.
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+    let (epoch1, version1, release1) = parse_evr(a);
+    let (epoch2, version2, release2) = parse_evr(b);
+    rpmvercmp(epoch1, epoch2)
+        .then_with(|| rpmvercmp(version1, version2))
+        .then_with(|| match (release1, release2) {
+            (Some(release1), Some(release2)) => rpmvercmp(release1, release2),
+            _ => Ordering::Equal,
+        })
+}
+
+fn parse_evr(evr: &str) -> (&str, &str, Option<&str>) {
+    let (epoch, version, release) = split_evr(evr);
+    (
+        epoch.filter(|epoch| !epoch.is_empty()).unwrap_or("0"),
+        version,
+        release,
+    )
+}
+
+/// Splits `[epoch:]pkgver[-pkgrel]` into its parts as written.
+pub fn split_evr(evr: &str) -> (Option<&str>, &str, Option<&str>) {
+    let digits = evr.bytes().take_while(u8::is_ascii_digit).count();
+    let (epoch, rest) = match evr[digits..].strip_prefix(':') {
+        Some(rest) => (Some(&evr[..digits]), rest),
+        None => (None, evr),
+    };
+    match rest.rfind('-') {
+        Some(i) => (epoch, &rest[..i], Some(&rest[i + 1..])),
+        None => (epoch, rest, None),
+    }
+}
+
+/// Splits a versioned dependency like `foo>=1:1.2-1` into the package name, the comparison
+/// operator and the version it compares against.
+pub fn split_dependency(dependency: &str) -> Option<(&str, &str, &str)> {
+    let i = dependency.find(['<', '>', '='])?;
+    let rest = &dependency[i..];
+    let len = if rest.starts_with(">=") || rest.starts_with("<=") {
+        2
+    } else {
+        1
+    };
+    Some((&dependency[..i], &rest[..len], &rest[len..]))
+}
+
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut one, mut two) = (0, 0);
+    let (mut end1, mut end2) = (0, 0);
+    while one < a.len() && two < b.len() {
+        while one < a.len() && !a[one].is_ascii_alphanumeric() {
+            one += 1;
+        }
+        while two < b.len() && !b[two].is_ascii_alphanumeric() {
+            two += 1;
+        }
+        if one == a.len() || two == b.len() {
+            break;
+        }
+        if one - end1 != two - end2 {
+            return (one - end1).cmp(&(two - end2));
+        }
+
+        let is_num = a[one].is_ascii_digit();
+        let class = |c: &u8| {
+            if is_num {
+                c.is_ascii_digit()
+            } else {
+                c.is_ascii_alphabetic()
+            }
+        };
+        end1 = one + a[one..].iter().take_while(|c| class(c)).count();
+        end2 = two + b[two..].iter().take_while(|c| class(c)).count();
+        if two == end2 {
+            return if is_num {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+
+        let (mut segment1, mut segment2) = (&a[one..end1], &b[two..end2]);
+        if is_num {
+            while let Some((b'0', rest)) = segment1.split_first() {
+                segment1 = rest;
+            }
+            while let Some((b'0', rest)) = segment2.split_first() {
+                segment2 = rest;
+            }
+            match segment1.len().cmp(&segment2.len()) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+        }
+        match segment1.cmp(segment2) {
+            Ordering::Equal => {}
+            ordering => return ordering,
+        }
+        one = end1;
+        two = end2;
+    }
+
+    if one == a.len() && two == b.len() {
+        Ordering::Equal
+    } else if (one == a.len() && !b[two].is_ascii_alphabetic())
+        || (one < a.len() && a[one].is_ascii_alphabetic())
+    {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}